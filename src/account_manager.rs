@@ -4,10 +4,14 @@ use serde::{Deserialize, Serialize};
 use solana_program::account_info::AccountInfo;
 use solana_program::pubkey::Pubkey;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::io::ErrorKind::NotFound;
+use std::io::Write;
 use std::rc::Rc;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use crate::state_tree::{self, Node, NodeStore};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, str::FromStr};
 
 static mut ACCOUNT_INFO_DATA: Vec<Vec<u8>> = Vec::new();
@@ -129,21 +133,303 @@ pub fn create_account_info<'a>(
     }
 }
 
-pub fn create_account_manager() -> AccountManager {
+/// Where the accounts are persisted.
+///
+/// `AccountManager` keeps one JSON file per account, `MemoryAccountStore`
+/// keeps them in memory, which is handy for tests. Programs called through
+/// CPI run in another process, so they only see file stores.
+pub trait AccountStore: std::fmt::Debug + Send + Sync {
+    fn read_account(
+        &self,
+        pubkey: &Pubkey,
+    ) -> std::result::Result<AccountFileData, Box<dyn std::error::Error>>;
+
+    fn write_account(
+        &self,
+        pubkey: &Pubkey,
+        account_file_data: &AccountFileData,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>>;
+
+    /// Deleting an account that doesn't exist is not an error.
+    fn delete_account(&self, pubkey: &Pubkey) -> std::result::Result<(), Box<dyn std::error::Error>>;
+
+    /// Writes all the accounts of an input, `None` deletes the account. The
+    /// file store journals them first, so a crash can't apply only some.
+    fn commit_accounts(&self, writes: &[AccountWrite]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        apply_writes(self, writes)
+    }
+
+    /// The accounts owned by the program.
+    fn find_program_accounts(
+        &self,
+        pubkey: &Pubkey,
+    ) -> std::result::Result<Vec<(Pubkey, AccountFileData)>, Box<dyn std::error::Error>>;
+
+    /// Like `getProgramAccounts`: the accounts owned by the program that match
+    /// all the filters, with their data projected by `data_slice`.
+    fn find_program_accounts_with_filters(
+        &self,
+        pubkey: &Pubkey,
+        filters: &[ProgramAccountsFilter],
+        data_slice: Option<DataSlice>,
+    ) -> std::result::Result<Vec<(Pubkey, AccountFileData)>, Box<dyn std::error::Error>> {
+        let accounts = self.find_program_accounts(pubkey)?;
+        Ok(filter_program_accounts(accounts.into_iter(), filters, data_slice))
+    }
+
+    /// The root of the `state_tree` over all the accounts, kept up to date by
+    /// `write_account` and `delete_account`.
+    fn state_root(&self) -> std::result::Result<state_tree::Hash32, Box<dyn std::error::Error>>;
+
+    /// Proves the account against `state_root`, or that it doesn't exist.
+    fn prove_account(&self, pubkey: &Pubkey) -> std::result::Result<AccountProof, Box<dyn std::error::Error>>;
+}
+
+/// The new state of an account, `None` when it is deleted.
+pub type AccountWrite = (Pubkey, Option<AccountFileData>);
+
+fn apply_writes<S: AccountStore + ?Sized>(
+    store: &S,
+    writes: &[AccountWrite],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    for (pubkey, account_file_data) in writes.iter() {
+        match account_file_data {
+            Some(account_file_data) => store.write_account(pubkey, account_file_data)?,
+            None => store.delete_account(pubkey)?,
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccountProof {
+    pub pubkey: Pubkey,
+    /// `None` for a proof that the account doesn't exist
+    pub account: Option<AccountFileData>,
+    pub proof: state_tree::Proof,
+}
+
+impl AccountProof {
+    fn new(pubkey: &Pubkey, account: Option<AccountFileData>, proof: state_tree::Proof) -> Self {
+        Self {
+            pubkey: *pubkey,
+            account,
+            proof,
+        }
+    }
+
+    pub fn leaf(&self) -> state_tree::Hash32 {
+        match &self.account {
+            Some(account) => state_tree::leaf_hash(&self.pubkey, account),
+            None => state_tree::ZERO_HASH,
+        }
+    }
+
+    pub fn verify(&self, state_root: &state_tree::Hash32) -> bool {
+        self.proof.compute_root(&self.pubkey, &self.leaf()).as_ref() == Some(state_root)
+    }
+
+    /// `abi.encode(bytes32 pubkey, bool exists, bytes32 owner, uint64 lamports,
+    /// bytes data, uint256 bitmap, bytes32[] siblings)`, e.g. for a notice.
+    pub fn abi_encode(&self) -> Vec<u8> {
+        fn word(value: u64) -> [u8; 32] {
+            let mut word = [0u8; 32];
+            word[24..].copy_from_slice(&value.to_be_bytes());
+            word
+        }
+        let (owner, lamports, data) = match &self.account {
+            Some(account) => (account.owner.to_bytes(), account.lamports, &account.data[..]),
+            None => (state_tree::ZERO_HASH, 0, &[][..]),
+        };
+        let head_size = 7 * 32;
+        let data_size = 32 + data.len().div_ceil(32) * 32;
+        let mut encoded = vec![];
+        encoded.extend_from_slice(self.pubkey.as_ref());
+        encoded.extend_from_slice(&word(self.account.is_some() as u64));
+        encoded.extend_from_slice(&owner);
+        encoded.extend_from_slice(&word(lamports));
+        encoded.extend_from_slice(&word(head_size as u64));
+        encoded.extend_from_slice(&self.proof.bitmap);
+        encoded.extend_from_slice(&word((head_size + data_size) as u64));
+        encoded.extend_from_slice(&word(data.len() as u64));
+        encoded.extend_from_slice(data);
+        encoded.resize(head_size + data_size, 0);
+        encoded.extend_from_slice(&word(self.proof.siblings.len() as u64));
+        for sibling in self.proof.siblings.iter() {
+            encoded.extend_from_slice(sibling);
+        }
+        encoded
+    }
+}
+
+/// The state root of the current store, e.g. after `persist_accounts`.
+pub fn get_state_root() -> state_tree::Hash32 {
+    create_account_manager()
+        .state_root()
+        .expect("failed to read the state root")
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProgramAccountsFilter {
+    /// The data has these bytes at the offset
+    Memcmp { offset: usize, bytes: Vec<u8> },
+    DataSize(usize),
+}
+
+impl ProgramAccountsFilter {
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            ProgramAccountsFilter::Memcmp { offset, bytes } => data
+                .get(*offset..offset.saturating_add(bytes.len()))
+                .is_some_and(|slice| slice == &bytes[..]),
+            ProgramAccountsFilter::DataSize(size) => data.len() == *size,
+        }
+    }
+}
+
+/// Only `length` bytes of the data from `offset`, truncated at the end of the data.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DataSlice {
+    pub offset: usize,
+    pub length: usize,
+}
+
+impl DataSlice {
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        let start = self.offset.min(data.len());
+        let end = self.offset.saturating_add(self.length).min(data.len());
+        data[start..end].to_vec()
+    }
+}
+
+fn filter_program_accounts(
+    accounts: impl Iterator<Item = (Pubkey, AccountFileData)>,
+    filters: &[ProgramAccountsFilter],
+    data_slice: Option<DataSlice>,
+) -> Vec<(Pubkey, AccountFileData)> {
+    accounts
+        .filter(|(_, account)| filters.iter().all(|filter| filter.matches(&account.data)))
+        .map(|(key, mut account)| {
+            if let Some(data_slice) = data_slice {
+                account.data = data_slice.apply(&account.data);
+            }
+            (key, account)
+        })
+        .collect()
+}
+
+lazy_static::lazy_static! {
+    static ref ACCOUNT_STORE: Mutex<Option<Arc<dyn AccountStore>>> = Mutex::new(None);
+}
+
+/// Replaces the file store for the whole process, until `reset_account_store`.
+pub fn set_account_store(account_store: Arc<dyn AccountStore>) {
+    *ACCOUNT_STORE.lock().unwrap() = Some(account_store);
+}
+
+/// Goes back to the file store under `SOLANA_DATA_PATH`.
+pub fn reset_account_store() {
+    *ACCOUNT_STORE.lock().unwrap() = None;
+}
+
+/// The store set by `set_account_store` or a file store under `SOLANA_DATA_PATH`.
+pub fn create_account_manager() -> Arc<dyn AccountStore> {
+    if let Some(account_store) = ACCOUNT_STORE.lock().unwrap().as_ref() {
+        return account_store.clone();
+    }
+    Arc::new(create_file_account_manager())
+}
+
+/// The file store under `SOLANA_DATA_PATH`, e.g. to manage its snapshots.
+pub fn create_file_account_manager() -> AccountManager {
     let mut account_manager = AccountManager::new().unwrap();
     let result = std::env::var("SOLANA_DATA_PATH");
     match result {
         Ok(path) => {
             //println!("base path from env {}", path);
             account_manager.set_base_path(path);
-            return account_manager;
         }
         Err(_) => {
             println!("default base path");
             account_manager.set_base_path("./".to_owned());
-            return account_manager;
         }
     };
+    account_manager
+        .recover_snapshot_restore()
+        .expect("failed to finish restoring a snapshot");
+    account_manager
+        .recover_journal()
+        .expect("failed to finish the last commit");
+    account_manager
+}
+
+/// Replaces the file in one step, so a crash never leaves it half written
+/// and the hard links of the snapshots keep the previous contents. The file
+/// and then its directory are synced, so a power loss doesn't undo it either.
+fn write_file_atomically(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    let result = match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    };
+    match result {
+        Err(error) if error.kind() != NotFound => Err(error),
+        _ => Ok(()),
+    }
+}
+
+/// Hard links the file, or every file under the directory, from `from` to
+/// `to`, copying them when the file system can't link.
+fn link_tree(from: &Path, to: &Path) -> std::io::Result<()> {
+    if !from.is_dir() {
+        if fs::hard_link(from, to).is_err() {
+            fs::copy(from, to)?;
+        }
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        link_tree(&entry.path(), &to.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+/// The entries of a data directory that belong to the store: the account
+/// files and the `.owners` and `.state` indexes. Anything else, like the
+/// snapshots themselves, is left alone by the snapshots.
+fn store_entries(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = vec![];
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let is_index = path.is_dir() && (path.ends_with(OWNER_INDEX_DIR) || path.ends_with(STATE_TREE_DIR));
+        if is_index || account_file_key(&path).is_some() {
+            entries.push(path);
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+    pub state_root: state_tree::Hash32,
 }
 
 #[derive(Debug)]
@@ -158,18 +444,314 @@ impl AccountManager {
         })
     }
 
-    pub fn find_program_accounts(
+    pub fn set_base_path(&mut self, base_path: String) {
+        self.base_path = base_path;
+    }
+
+    /// A snapshot per directory: `.snapshots/<name>/snapshot.json` and the
+    /// `store_entries` linked under `.snapshots/<name>/data`
+    fn snapshots_path(&self) -> PathBuf {
+        Path::new(&self.base_path).join(SNAPSHOTS_DIR)
+    }
+
+    fn snapshot_path(&self, name: &str) -> std::result::Result<PathBuf, Box<dyn std::error::Error>> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            let message = format!("invalid snapshot name: {:?}", name);
+            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, message)));
+        }
+        Ok(self.snapshots_path().join(name))
+    }
+
+    /// The snapshots replace files in the data directory, so it can't be the
+    /// working directory that `create_file_account_manager` defaults to.
+    fn check_snapshot_base_path(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        if Path::new(&self.base_path).components().all(|component| component == Component::CurDir) {
+            let message = "snapshots need a data directory, set SOLANA_DATA_PATH";
+            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::InvalidInput, message)));
+        }
+        Ok(())
+    }
+
+    /// Names the snapshot being restored, until all of its files are in place.
+    fn restore_marker_path(&self) -> PathBuf {
+        self.snapshots_path().join(".restore")
+    }
+
+    /// Saves the current accounts as `name`, the files are hard linked so it
+    /// is cheap, and `write_account` replaces files instead of changing them.
+    pub fn create_snapshot(&self, name: &str) -> std::result::Result<SnapshotInfo, Box<dyn std::error::Error>> {
+        self.check_snapshot_base_path()?;
+        let snapshot_path = self.snapshot_path(name)?;
+        if snapshot_path.exists() {
+            let message = format!("snapshot already exists: {}", name);
+            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message)));
+        }
+        let info = SnapshotInfo {
+            name: name.to_string(),
+            created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            state_root: self.state_root()?,
+        };
+        // only the rename makes it visible
+        let tmp_path = self.snapshots_path().join(format!(".tmp-{}", name));
+        remove_path(&tmp_path)?;
+        fs::create_dir_all(tmp_path.join("data"))?;
+        for path in store_entries(Path::new(&self.base_path))? {
+            link_tree(&path, &tmp_path.join("data").join(path.file_name().unwrap()))?;
+        }
+        fs::write(tmp_path.join("snapshot.json"), serde_json::to_string(&info)?)?;
+        fs::rename(&tmp_path, &snapshot_path)?;
+        Ok(info)
+    }
+
+    /// The snapshots, oldest first.
+    pub fn list_snapshots(&self) -> std::result::Result<Vec<SnapshotInfo>, Box<dyn std::error::Error>> {
+        let mut snapshots: Vec<SnapshotInfo> = vec![];
+        if !self.snapshots_path().exists() {
+            return Ok(snapshots);
+        }
+        for entry in fs::read_dir(self.snapshots_path())? {
+            let entry = entry?;
+            let info_path = entry.path().join("snapshot.json");
+            // the hidden ones are unfinished
+            if entry.file_name().to_string_lossy().starts_with('.') || !info_path.exists() {
+                continue;
+            }
+            snapshots.push(serde_json::from_str(&fs::read_to_string(info_path)?)?);
+        }
+        snapshots.sort_by(|a, b| (a.created_at, &a.name).cmp(&(b.created_at, &b.name)));
+        Ok(snapshots)
+    }
+
+    /// Replaces all the accounts with the ones of the snapshot. A crash in
+    /// the middle is finished by `recover_snapshot_restore`, so the data
+    /// directory ends up either as before or as the snapshot.
+    pub fn restore_snapshot(&self, name: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.check_snapshot_base_path()?;
+        let snapshot_path = self.snapshot_path(name)?;
+        if !snapshot_path.join("snapshot.json").exists() {
+            let message = format!("snapshot not found: {}", name);
+            return Err(Box::new(std::io::Error::new(NotFound, message)));
+        }
+        write_file_atomically(&self.restore_marker_path(), name)?;
+        self.recover_snapshot_restore()
+    }
+
+    /// Finishes a restore interrupted by a crash, called by `create_file_account_manager`.
+    pub fn recover_snapshot_restore(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let name = match fs::read_to_string(self.restore_marker_path()) {
+            Ok(name) => name,
+            Err(error) if error.kind() == NotFound => return Ok(()),
+            Err(error) => return Err(Box::new(error)),
+        };
+        println!("restoring snapshot {}", name);
+        for path in store_entries(Path::new(&self.base_path))? {
+            remove_path(&path)?;
+        }
+        let data_path = self.snapshot_path(&name)?.join("data");
+        for path in store_entries(&data_path)? {
+            link_tree(&path, &Path::new(&self.base_path).join(path.file_name().unwrap()))?;
+        }
+        fs::remove_file(self.restore_marker_path())?;
+        Ok(())
+    }
+
+    /// The writes of the commit in progress, the commit happens when the
+    /// journal is in place and it is removed after all of them are applied.
+    fn journal_path(&self) -> PathBuf {
+        Path::new(&self.base_path).join(JOURNAL_FILE)
+    }
+
+    /// Applies the writes of a commit interrupted by a crash, called by
+    /// `create_file_account_manager`. Applying them again is harmless, a
+    /// journal is never half written. The indexes aren't journaled and the
+    /// crash may have left them half updated, so they are rebuilt from the
+    /// account files first.
+    pub fn recover_journal(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let contents = match fs::read_to_string(self.journal_path()) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == NotFound => return Ok(()),
+            Err(error) => return Err(Box::new(error)),
+        };
+        let writes: Vec<AccountWrite> = serde_json::from_str(&contents)?;
+        println!("recovering a commit of {} accounts", writes.len());
+        remove_path(&self.owner_index_path())?;
+        remove_path(&self.state_tree_path())?;
+        self.ensure_indexes()?;
+        self.apply_journal(&writes)
+    }
+
+    fn apply_journal(&self, writes: &[AccountWrite]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        apply_writes(self, writes)?;
+        fs::remove_file(self.journal_path())?;
+        Ok(())
+    }
+
+    pub fn delete_snapshot(&self, name: &str) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        remove_path(&self.snapshot_path(name)?)?;
+        Ok(())
+    }
+
+    /// Deletes all but the `keep` most recent snapshots, returns the deleted ones.
+    pub fn prune_snapshots(&self, keep: usize) -> std::result::Result<Vec<SnapshotInfo>, Box<dyn std::error::Error>> {
+        let mut snapshots = self.list_snapshots()?;
+        let pruned: Vec<SnapshotInfo> = snapshots.drain(..snapshots.len().saturating_sub(keep)).collect();
+        for snapshot in pruned.iter() {
+            self.delete_snapshot(&snapshot.name)?;
+        }
+        Ok(pruned)
+    }
+
+    fn read_account_file(
+        &self,
+        file_path: String,
+    ) -> std::result::Result<AccountFileData, Box<dyn std::error::Error>> {
+        let read = fs::read_to_string(&file_path);
+        match read {
+            Ok(contents) => {
+                let account = serde_json::from_str::<AccountFileData>(&contents)?;
+                Ok(account)
+            }
+            Err(error) => {
+                println!("Account not found: {:?}", file_path);
+                Err(Box::new(error))
+            },
+        }
+    }
+
+    /// The owner index has an empty file per account, under a directory per owner:
+    /// `.owners/<owner>/<key>`
+    fn owner_index_path(&self) -> PathBuf {
+        Path::new(&self.base_path).join(OWNER_INDEX_DIR)
+    }
+
+    fn owner_index_entry(&self, owner: &Pubkey, pubkey: &Pubkey) -> PathBuf {
+        self.owner_index_path()
+            .join(owner.to_string())
+            .join(pubkey.to_string())
+    }
+
+    /// The state tree nodes, a file per node: `.state/<height>-<prefix>.json`
+    fn state_tree_path(&self) -> PathBuf {
+        Path::new(&self.base_path).join(STATE_TREE_DIR)
+    }
+
+    fn state_tree_node_path(&self, height: usize, prefix: &state_tree::Hash32) -> PathBuf {
+        self.state_tree_path()
+            .join(format!("{}-{}.json", height, hex::encode(prefix)))
+    }
+
+    /// The accounts persisted before the indexes existed are indexed on first use.
+    fn ensure_indexes(&self) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let missing_owner_index = !self.owner_index_path().exists();
+        let missing_state_tree = !self.state_tree_path().exists();
+        if !missing_owner_index && !missing_state_tree {
+            return Ok(());
+        }
+        fs::create_dir_all(self.owner_index_path())?;
+        fs::create_dir_all(self.state_tree_path())?;
+        for path in fs::read_dir(&self.base_path)? {
+            let file_path = path?.path();
+            let pubkey = match account_file_key(&file_path) {
+                Some(pubkey) => pubkey,
+                None => continue,
+            };
+            match self.read_account_file(file_path.to_str().unwrap().to_string()) {
+                Ok(account) => {
+                    if missing_owner_index {
+                        self.add_to_owner_index(&account.owner, &pubkey)?;
+                    }
+                    if missing_state_tree {
+                        state_tree::update(self, &pubkey, Some(state_tree::leaf_hash(&pubkey, &account)))?;
+                    }
+                }
+                Err(error) => println!("skipping {:?}: {}", file_path, error),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_to_owner_index(&self, owner: &Pubkey, pubkey: &Pubkey) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let entry = self.owner_index_entry(owner, pubkey);
+        fs::create_dir_all(entry.parent().unwrap())?;
+        write_file_atomically(&entry, [])?;
+        Ok(())
+    }
+
+    fn remove_from_owner_index(&self, owner: &Pubkey, pubkey: &Pubkey) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        match fs::remove_file(self.owner_index_entry(owner, pubkey)) {
+            Err(error) if error.kind() != NotFound => Err(Box::new(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+const OWNER_INDEX_DIR: &str = ".owners";
+const STATE_TREE_DIR: &str = ".state";
+const SNAPSHOTS_DIR: &str = ".snapshots";
+const JOURNAL_FILE: &str = ".journal.json";
+
+impl NodeStore for AccountManager {
+    fn get_node(
+        &self,
+        height: usize,
+        prefix: &state_tree::Hash32,
+    ) -> std::result::Result<Option<Node>, Box<dyn std::error::Error>> {
+        match fs::read_to_string(self.state_tree_node_path(height, prefix)) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(error) if error.kind() == NotFound => Ok(None),
+            Err(error) => Err(Box::new(error)),
+        }
+    }
+
+    fn put_node(
+        &self,
+        height: usize,
+        prefix: &state_tree::Hash32,
+        node: &Node,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        write_file_atomically(&self.state_tree_node_path(height, prefix), serde_json::to_string(node)?)?;
+        Ok(())
+    }
+
+    fn delete_node(&self, height: usize, prefix: &state_tree::Hash32) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        match fs::remove_file(self.state_tree_node_path(height, prefix)) {
+            Err(error) if error.kind() != NotFound => Err(Box::new(error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The key of a `<pubkey>.json` file, anything else isn't an account.
+fn account_file_key(file_path: &Path) -> Option<Pubkey> {
+    if !file_path.is_file() || file_path.extension()? != "json" {
+        return None;
+    }
+    Pubkey::from_str(file_path.file_stem()?.to_str()?).ok()
+}
+
+impl AccountStore for AccountManager {
+    /// Sorted by key, so the result doesn't depend on the file system.
+    fn find_program_accounts(
         &self,
         pubkey: &Pubkey,
     ) -> std::result::Result<Vec<(Pubkey, AccountFileData)>, Box<dyn std::error::Error>> {
-        let paths = fs::read_dir(&self.base_path)?;
+        self.ensure_indexes()?;
+        let owner_path = self.owner_index_path().join(pubkey.to_string());
+        if !owner_path.exists() {
+            return Ok(vec![]);
+        }
+        let mut keys: Vec<Pubkey> = vec![];
+        for path in fs::read_dir(owner_path)? {
+            let file_name = path?.file_name();
+            if let Some(pk) = file_name.to_str().and_then(|key| Pubkey::from_str(key).ok()) {
+                keys.push(pk);
+            }
+        }
+        keys.sort();
         let mut result: Vec<(Pubkey, AccountFileData)> = vec![];
-        for path in paths {
-            let file_path = path?.path();
-            let account_info = self.read_account_file(file_path.to_str().unwrap().to_string())?;
+        for pk in keys {
+            let account_info = self.read_account(&pk)?;
             if account_info.owner == *pubkey {
-                let key = file_path.file_name().unwrap().to_str().unwrap();
-                let pk = Pubkey::from_str(&key[..(key.len() - 5)]).unwrap();
                 println!("program {:?} owns {:?}", &pubkey, &pk);
                 result.push((pk, account_info));
             }
@@ -177,18 +759,22 @@ impl AccountManager {
         Ok(result)
     }
 
-    pub fn set_base_path(&mut self, base_path: String) {
-        self.base_path = base_path;
-    }
-
-    pub fn write_account(
+    fn write_account(
         &self,
         pubkey: &Pubkey,
         account_file_data: &AccountFileData,
     ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.ensure_indexes()?;
+        if let Ok(previous) = self.read_account(pubkey) {
+            if previous.owner != account_file_data.owner {
+                self.remove_from_owner_index(&previous.owner, pubkey)?;
+            }
+        }
         let file_path = format!("{}/{}.json", &self.base_path, pubkey.to_string());
         let contents = serde_json::to_string(account_file_data)?;
-        fs::write(file_path, contents)?;
+        write_file_atomically(Path::new(&file_path), contents)?;
+        self.add_to_owner_index(&account_file_data.owner, pubkey)?;
+        state_tree::update(self, pubkey, Some(state_tree::leaf_hash(pubkey, account_file_data)))?;
         println!(
             "saved {:?}; data.len() = {}",
             pubkey,
@@ -197,7 +783,7 @@ impl AccountManager {
         Ok(())
     }
 
-    pub fn read_account(
+    fn read_account(
         &self,
         pubkey: &Pubkey,
     ) -> std::result::Result<AccountFileData, Box<dyn std::error::Error>> {
@@ -205,27 +791,15 @@ impl AccountManager {
         self.read_account_file(file_path)
     }
 
-    fn read_account_file(
-        &self,
-        file_path: String,
-    ) -> std::result::Result<AccountFileData, Box<dyn std::error::Error>> {
-        let read = fs::read_to_string(&file_path);
-        match read {
-            Ok(contents) => {
-                let account = serde_json::from_str::<AccountFileData>(&contents)?;
-                Ok(account)
-            }
-            Err(error) => {
-                println!("Account not found: {:?}", file_path);
-                Err(Box::new(error))
-            },
-        }
-    }
-
-    pub fn delete_account(
+    fn delete_account(
         &self,
         pubkey: &Pubkey,
     ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.ensure_indexes()?;
+        if let Ok(previous) = self.read_account(pubkey) {
+            self.remove_from_owner_index(&previous.owner, pubkey)?;
+            state_tree::update(self, pubkey, None)?;
+        }
         let file_path = format!("{}/{}.json", &self.base_path, pubkey.to_string());
         let delete_result = fs::remove_file(file_path);
         match delete_result {
@@ -241,9 +815,128 @@ impl AccountManager {
             }
         }
     }
+
+    fn commit_accounts(&self, writes: &[AccountWrite]) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        write_file_atomically(&self.journal_path(), serde_json::to_string(writes)?)?;
+        self.apply_journal(writes)
+    }
+
+    fn state_root(&self) -> std::result::Result<state_tree::Hash32, Box<dyn std::error::Error>> {
+        self.ensure_indexes()?;
+        state_tree::root(self)
+    }
+
+    fn prove_account(&self, pubkey: &Pubkey) -> std::result::Result<AccountProof, Box<dyn std::error::Error>> {
+        self.ensure_indexes()?;
+        let proof = state_tree::prove(self, pubkey)?;
+        Ok(AccountProof::new(pubkey, self.read_account(pubkey).ok(), proof))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryAccountStore {
+    accounts: Mutex<BTreeMap<Pubkey, AccountFileData>>,
+    nodes: Mutex<BTreeMap<(usize, state_tree::Hash32), Node>>,
+}
+
+impl NodeStore for MemoryAccountStore {
+    fn get_node(
+        &self,
+        height: usize,
+        prefix: &state_tree::Hash32,
+    ) -> std::result::Result<Option<Node>, Box<dyn std::error::Error>> {
+        Ok(self.nodes.lock().unwrap().get(&(height, *prefix)).cloned())
+    }
+
+    fn put_node(
+        &self,
+        height: usize,
+        prefix: &state_tree::Hash32,
+        node: &Node,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.nodes.lock().unwrap().insert((height, *prefix), node.clone());
+        Ok(())
+    }
+
+    fn delete_node(&self, height: usize, prefix: &state_tree::Hash32) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.nodes.lock().unwrap().remove(&(height, *prefix));
+        Ok(())
+    }
+}
+
+impl MemoryAccountStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn read_account(
+        &self,
+        pubkey: &Pubkey,
+    ) -> std::result::Result<AccountFileData, Box<dyn std::error::Error>> {
+        match self.accounts.lock().unwrap().get(pubkey) {
+            Some(account_file_data) => Ok(account_file_data.clone()),
+            None => {
+                println!("Account not found: {:?}", pubkey);
+                Err(Box::new(std::io::Error::from(NotFound)))
+            }
+        }
+    }
+
+    fn write_account(
+        &self,
+        pubkey: &Pubkey,
+        account_file_data: &AccountFileData,
+    ) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        self.accounts
+            .lock()
+            .unwrap()
+            .insert(*pubkey, account_file_data.clone());
+        state_tree::update(self, pubkey, Some(state_tree::leaf_hash(pubkey, account_file_data)))
+    }
+
+    fn delete_account(&self, pubkey: &Pubkey) -> std::result::Result<(), Box<dyn std::error::Error>> {
+        if self.accounts.lock().unwrap().remove(pubkey).is_some() {
+            state_tree::update(self, pubkey, None)?;
+        }
+        Ok(())
+    }
+
+    fn state_root(&self) -> std::result::Result<state_tree::Hash32, Box<dyn std::error::Error>> {
+        state_tree::root(self)
+    }
+
+    fn prove_account(&self, pubkey: &Pubkey) -> std::result::Result<AccountProof, Box<dyn std::error::Error>> {
+        let proof = state_tree::prove(self, pubkey)?;
+        Ok(AccountProof::new(pubkey, self.read_account(pubkey).ok(), proof))
+    }
+
+    fn find_program_accounts(
+        &self,
+        pubkey: &Pubkey,
+    ) -> std::result::Result<Vec<(Pubkey, AccountFileData)>, Box<dyn std::error::Error>> {
+        self.find_program_accounts_with_filters(pubkey, &[], None)
+    }
+
+    fn find_program_accounts_with_filters(
+        &self,
+        pubkey: &Pubkey,
+        filters: &[ProgramAccountsFilter],
+        data_slice: Option<DataSlice>,
+    ) -> std::result::Result<Vec<(Pubkey, AccountFileData)>, Box<dyn std::error::Error>> {
+        let accounts = self.accounts.lock().unwrap();
+        // filters before cloning, the projection is applied to the copies
+        let matching = accounts
+            .iter()
+            .filter(|(_, account_file_data)| account_file_data.owner == *pubkey)
+            .filter(|(_, account_file_data)| filters.iter().all(|filter| filter.matches(&account_file_data.data)))
+            .map(|(key, account_file_data)| (*key, account_file_data.clone()));
+        Ok(filter_program_accounts(matching, &[], data_slice))
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AccountFileData {
     /**
      * program owner
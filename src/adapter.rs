@@ -1,17 +1,20 @@
-use crate::account_manager::{self, create_account_info, create_account_manager, AccountFileData};
-use crate::cartesi_stub::AccountInfoSerialize;
-use crate::{cpi, owner_manager, transaction};
+use crate::account_manager::{self, create_account_info, create_account_manager, AccountFileData, AccountWrite};
+use crate::cartesi_stub::{current_rent, metadata_slot, rent_enforced, AccountInfoSerialize};
+use crate::{address_lookup_table, compute_budget, cpi, log_collector, owner_manager, sysvar_accounts, transaction};
 use serde::{Deserialize, Serialize};
 use solana_program::account_info::AccountInfo;
 use solana_program::entrypoint::ProgramResult;
 use solana_program::instruction::Instruction;
+use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
 use std::io;
 use std::str::FromStr;
 use std::sync::Mutex;
 
 lazy_static::lazy_static! {
     static ref TIMESTAMP: Mutex<i64> = Mutex::new(0);
+    static ref INPUT_METADATA: Mutex<Option<InputMetadata>> = Mutex::new(None);
 }
 
 pub fn set_timestamp(value: i64) {
@@ -22,6 +25,70 @@ pub fn get_timestamp() -> i64 {
     *TIMESTAMP.lock().unwrap()
 }
 
+/// The rollup metadata of the input, sent after its timestamp.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputMetadata {
+    pub block_number: u64,
+    pub input_index: u64,
+    pub epoch_index: u64,
+}
+
+pub fn set_input_metadata(value: Option<InputMetadata>) {
+    *INPUT_METADATA.lock().unwrap() = value;
+}
+
+pub fn get_input_metadata() -> Option<InputMetadata> {
+    *INPUT_METADATA.lock().unwrap()
+}
+
+/// Sets the timestamp and the metadata from the timestamp line of an input:
+/// `<timestamp>` or `<timestamp> <block number> <input index> <epoch index>`.
+/// Metadata that doesn't fit in a slot (see `cartesi_stub::metadata_slot`)
+/// is rejected with `InvalidArgument`.
+pub fn set_timestamp_line(line: &str) -> Result<(), ProgramError> {
+    let mut values = line.split_whitespace();
+    let timestamp: i64 = values
+        .next()
+        .unwrap_or_default()
+        .parse()
+        .expect("Timestamp is not an integer");
+    set_timestamp(timestamp);
+    let metadata: Vec<u64> = values
+        .map(|value| value.parse().expect("Input metadata is not an integer"))
+        .collect();
+    let metadata = match metadata[..] {
+        [] => None,
+        [block_number, input_index, epoch_index] => Some(InputMetadata {
+            block_number,
+            input_index,
+            epoch_index,
+        }),
+        _ => panic!("Expected the block number, input index and epoch index after the timestamp"),
+    };
+    if let Some(metadata) = metadata {
+        if metadata_slot(&metadata).is_none() {
+            println!("Input metadata out of the range of the slots: {:?}", metadata);
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+    set_input_metadata(metadata);
+    Ok(())
+}
+
+/// The timestamp line of the current input, passed on to CPI calls.
+pub fn get_timestamp_line() -> String {
+    match get_input_metadata() {
+        Some(metadata) => format!(
+            "{} {} {} {}",
+            get_timestamp(),
+            metadata.block_number,
+            metadata.input_index,
+            metadata.epoch_index
+        ),
+        None => get_timestamp().to_string(),
+    }
+}
+
 pub fn get_binary_base_path() -> String {
     let result = std::env::var("SOLANA_BIN_PATH");
     match result {
@@ -30,6 +97,18 @@ pub fn get_binary_base_path() -> String {
     }
 }
 
+/// The address of this dApp on L1, signatures of relayed inputs are bound to it.
+pub fn get_dapp_address() -> [u8; 20] {
+    let address = std::env::var("DAPP_ADDRESS").expect("DAPP_ADDRESS is required for relayed inputs");
+    let bytes = hex::decode(address.trim_start_matches("0x")).expect("DAPP_ADDRESS is not hex");
+    bytes.try_into().expect("DAPP_ADDRESS must have 20 bytes len")
+}
+
+pub fn get_chain_id() -> u64 {
+    let chain_id = std::env::var("CHAIN_ID").expect("CHAIN_ID is required for relayed inputs");
+    chain_id.trim().parse().expect("CHAIN_ID is not an integer")
+}
+
 pub fn eth_address_to_pubkey(eth_address: &[u8]) -> Pubkey {
     assert!(
         eth_address.len() == 20,
@@ -55,7 +134,7 @@ fn get_read_line() -> Vec<u8> {
     }
 }
 
-fn get_processor_args_from_cpi<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>, bool) {
+fn get_processor_args_from_cpi<'a>() -> Result<(Pubkey, Vec<AccountInfo<'a>>, Vec<u8>, bool), ProgramError> {
     let instruction = get_read_line();
     let accounts = get_read_line();
     let signers_seed = get_read_line();
@@ -63,15 +142,22 @@ fn get_processor_args_from_cpi<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>,
     {
         let mut timestamp = String::new();
         io::stdin().read_line(&mut timestamp).unwrap();
-        let timestamp: i64 = timestamp
-            .trim()
-            .parse()
-            .expect("Timestamp is not an integer");
-        set_timestamp(timestamp);
+        set_timestamp_line(&timestamp)?;
     }
 
     let caller_program_id = get_read_line();
     let caller_program_id = Pubkey::new(&caller_program_id);
+    #[cfg(not(target_arch = "bpf"))]
+    {
+        let mut compute_units = String::new();
+        io::stdin().read_line(&mut compute_units).unwrap();
+        compute_budget::set_compute_budget_line(&compute_units);
+        let mut stack_height = String::new();
+        io::stdin().read_line(&mut stack_height).unwrap();
+        cpi::set_stack_height_line(&stack_height);
+        log_collector::reset_logs();
+        cpi::set_response_accounts(vec![]);
+    }
     let signers_seed: Vec<Vec<Vec<u8>>> = bincode::deserialize(&signers_seed).unwrap();
     let instruction: Instruction = bincode::deserialize(&instruction).unwrap();
     let accounts: Vec<AccountInfoSerialize> = bincode::deserialize(&accounts).unwrap();
@@ -85,7 +171,12 @@ fn get_processor_args_from_cpi<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>,
     let pda_signature: Vec<&[&[u8]]> = pda_signature.iter().map(|x| x.as_slice()).collect();
     let pda_signature: &[&[&[u8]]] = pda_signature.as_slice();
 
-    cpi::check_signature(&caller_program_id, &instruction, &pda_signature);
+    let caller_signers: Vec<Pubkey> = accounts
+        .iter()
+        .filter(|account| account.is_signer)
+        .map(|account| account.key)
+        .collect();
+    cpi::check_signature(&caller_program_id, &instruction, &caller_signers, pda_signature)?;
 
     println!("CPI accounts: {:?}", pubkeys);
     let accounts: Vec<AccountInfo<'a>> = accounts
@@ -103,12 +194,21 @@ fn get_processor_args_from_cpi<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>,
         })
         .collect();
 
-    let mut ordered_accounts = vec![];
-    for key in pubkeys.iter() {
-        let account_item = accounts.iter().find(|acc| acc.key == key);
+    let mut ordered_accounts: Vec<AccountInfo> = vec![];
+    for meta in instruction.accounts.iter() {
+        let account_item = accounts.iter().find(|acc| acc.key == &meta.pubkey);
         match account_item {
-            Some(account_info) => ordered_accounts.push(account_info.to_owned()),
-            None => panic!("Account not found {:?}", key),
+            Some(account_info) => {
+                if meta.is_writable && !account_info.is_writable {
+                    log_collector::log(&format!("{}'s writable privilege escalated", meta.pubkey));
+                    return Err(ProgramError::InvalidArgument);
+                }
+                let mut account_info = account_info.to_owned();
+                account_info.is_signer = meta.is_signer;
+                account_info.is_writable = meta.is_writable;
+                ordered_accounts.push(account_info);
+            }
+            None => panic!("Account not found {:?}", meta.pubkey),
         }
     }
 
@@ -123,12 +223,12 @@ fn get_processor_args_from_cpi<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>,
         owner_manager::add_ptr(p as *mut Pubkey, ordered_accounts[j].key.clone());
     }
 
-    (
+    Ok((
         instruction.program_id,
         ordered_accounts,
         instruction.data,
         true,
-    )
+    ))
 }
 
 #[cfg(not(target_arch = "bpf"))]
@@ -145,12 +245,7 @@ fn get_processor_args_from_external<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<
         .expect("Input is not an integer");
     let mut timestamp = String::new();
     io::stdin().read_line(&mut timestamp).unwrap();
-
-    let timestamp: i64 = timestamp
-        .trim()
-        .parse()
-        .expect("Timestamp is not an integer");
-    set_timestamp(timestamp);
+    set_timestamp_line(&timestamp).expect("Invalid input metadata");
 
     return parse_processor_args(
         &payload[..(&payload.len() - 1)],
@@ -168,7 +263,12 @@ pub fn get_processor_args<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>, bool)
 
         let tuple = match check_header(header.as_str()) {
             SmartContractType::ExternalPI => get_processor_args_from_external(),
-            SmartContractType::CPI => get_processor_args_from_cpi(),
+            SmartContractType::RelayedPI => panic!("Relayed inputs are only supported by the Executor"),
+            SmartContractType::CPI => get_processor_args_from_cpi().unwrap_or_else(|error| {
+                report_error(None, None, &error);
+                cpi::report_cpi_response(&Err(error.clone()));
+                panic!("{}", error);
+            }),
         };
         solana_program::program_stubs::set_syscall_stubs(Box::new(
             crate::cartesi_stub::CartesiStubs {
@@ -183,20 +283,81 @@ pub fn get_processor_args<'a>() -> (Pubkey, Vec<AccountInfo<'a>>, Vec<u8>, bool)
     }
 }
 
-type SolanaEntrypoint = fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult;
+pub type SolanaEntrypoint = fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult;
 
 fn call_smart_contract_cpi(solana_program_entrypoint: SolanaEntrypoint) -> io::Result<()> {
-    let (program_id, accounts, data, last_instruction) = get_processor_args_from_cpi();
-    let resp = solana_program_entrypoint(&program_id, &accounts, &data);
-    resp.unwrap();
-    // @todo maybe remove last_instruction
-    persist_accounts(&accounts, last_instruction);
+    let (program_id, accounts, data, _) = match get_processor_args_from_cpi() {
+        Ok(args) => args,
+        Err(error) => {
+            cpi::report_cpi_response(&Err(error.clone()));
+            report_error(None, None, &error);
+            return Err(io::Error::other(error.to_string()));
+        }
+    };
+    let resp = run_program(solana_program_entrypoint, &program_id, &accounts, &data);
+    if resp.is_ok() {
+        // persisted by the transaction of the caller, see `CpiResponse`
+        let response_accounts = accounts
+            .iter()
+            .filter(|acc| acc.is_writable && !sysvar_accounts::is_sysvar(acc.key))
+            .map(|acc| cpi::CpiAccount::new(acc.key, acc.owner, acc.lamports(), &acc.data.borrow()))
+            .collect();
+        cpi::set_response_accounts(response_accounts);
+    }
+    cpi::report_cpi_response(&resp);
+    if let Err(error) = resp {
+        report_error(Some(&program_id), None, &error);
+        return Err(io::Error::other(error.to_string()));
+    }
 
     Ok(())
 }
 
+/// Runs the program and checks its accounts, logged with the framing of `log_collector`.
+fn run_program(
+    solana_program_entrypoint: SolanaEntrypoint,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let readonly_snapshot = snapshot_readonly_accounts(accounts);
+    let rent_snapshot = snapshot_rent_state(accounts);
+    log_collector::program_invoke(program_id, cpi::get_stack_height());
+    cpi::reset_return_data(program_id);
+    let result = compute_budget::run_metered(|| solana_program_entrypoint(program_id, accounts, data))
+        .and_then(|_| check_readonly_accounts(accounts, &readonly_snapshot))
+        .and_then(|_| check_rent_exemption(accounts, &rent_snapshot));
+    log_collector::program_return_data();
+    let meter = compute_budget::get_compute_meter();
+    log_collector::program_consumed(program_id, meter.consumed, meter.limit);
+    log_collector::program_result(program_id, &result);
+    result
+}
+
+/// What is printed when a program fails, so the rollup can reject the input.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ErrorReport {
+    pub program_id: Option<String>,
+    pub instruction_index: Option<usize>,
+    pub error: String,
+    /// `ProgramError` as returned to the runtime, `Custom(n)` is `n`
+    pub code: u64,
+}
+
+/// Prints the error as a single `Error: {json}` line.
+pub fn report_error(program_id: Option<&Pubkey>, instruction_index: Option<usize>, error: &ProgramError) {
+    let report = ErrorReport {
+        program_id: program_id.map(|program_id| program_id.to_string()),
+        instruction_index,
+        error: error.to_string(),
+        code: u64::from(error.clone()),
+    };
+    println!("Error: {}", serde_json::to_string(&report).unwrap());
+}
+
 pub enum SmartContractType {
     ExternalPI,
+    RelayedPI,
     CPI,
 }
 
@@ -205,6 +366,8 @@ pub fn check_header(header: &str) -> SmartContractType {
 
     if header == "Header: External CPI" {
         SmartContractType::ExternalPI
+    } else if header == "Header: Relayed CPI" {
+        SmartContractType::RelayedPI
     } else if header == "Header: CPI" {
         SmartContractType::CPI
     } else {
@@ -213,7 +376,7 @@ pub fn check_header(header: &str) -> SmartContractType {
 }
 
 pub fn call_solana_cpi(entry: SolanaEntrypoint) -> io::Result<()> {
-    call_smart_contract_cpi(entry).unwrap();
+    call_smart_contract_cpi(entry)?;
 
     Ok(())
 }
@@ -234,19 +397,15 @@ fn call_solana_program_external(_entry: SolanaEntrypoint) -> io::Result<()> {
         .expect("Input is not an integer");
     let mut timestamp = String::new();
     io::stdin().read_line(&mut timestamp)?;
-
-    let timestamp: i64 = timestamp
-        .trim()
-        .parse()
-        .expect("Timestamp is not an integer");
-    set_timestamp(timestamp);
+    set_timestamp_line(&timestamp).map_err(|error| io::Error::other(error.to_string()))?;
 
     call_smart_contract_base64(
         &payload[..(&payload.len() - 1)],
         &msg_sender[..(&msg_sender.len() - 1)],
         instruction_index,
         _entry,
-    );
+    )
+    .map_err(|error| io::Error::other(error.to_string()))?;
 
     Ok(())
 }
@@ -264,12 +423,18 @@ pub fn call_solana_program(_entry: SolanaEntrypoint) -> io::Result<()> {
             SmartContractType::ExternalPI => {
                 call_solana_program_external(_entry)?;
             }
+            SmartContractType::RelayedPI => {
+                panic!("Relayed inputs are only supported by the Executor");
+            }
         }
     }
     Ok(())
 }
 
 pub fn load_account_info_data(pubkey: &Pubkey) -> (Vec<u8>, u64, Pubkey) {
+    if let Some(sysvar_account) = sysvar_accounts::load_sysvar_account(pubkey) {
+        return sysvar_account;
+    }
     let account_manager = create_account_manager();
     let read_account_data_file = account_manager.read_account(&pubkey);
     match read_account_data_file {
@@ -310,35 +475,59 @@ pub fn parse_processor_args<'a>(
     println!("payload => {:?}", payload);
     println!("instruction_index => {:?}", instruction_index);
     let decoded = base64::decode(payload).unwrap();
-    let tx: transaction::Transaction = bincode::deserialize(&decoded).unwrap();
+    let tx: transaction::VersionedTransaction = bincode::deserialize(&decoded).unwrap();
+    let (account_keys, loaded_addresses) = address_lookup_table::load_account_keys(&tx.message)
+        .expect("failed to load the address table lookups");
+    let instructions = tx.message.instructions();
     let sender_bytes: Vec<u8> = hex::decode(&msg_sender[2..])
         .unwrap()
         .into_iter()
         .rev()
         .collect();
-    let tx_instruction = &tx.message.instructions[instruction_index];
-    let last_instruction = instruction_index == &tx.message.instructions.len() - 1;
+    let tx_instruction = &instructions[instruction_index];
+    let last_instruction = instruction_index == instructions.len() - 1;
+    let (unit_limit, unit_price) = compute_budget::compute_budget_from_instructions(
+        instructions
+            .iter()
+            .map(|instruction| (&account_keys[instruction.program_id_index as usize], &instruction.data[..])),
+    )
+    .expect("invalid ComputeBudget instructions");
+    compute_budget::set_compute_budget(unit_limit, unit_price);
+    cpi::set_stack_height(1);
+    log_collector::reset_logs();
     let mut accounts: Vec<AccountInfo> = vec![];
-    for key in tx.message.account_keys.iter() {
+    for key in account_keys.iter() {
         let (data, lamports, owner) = load_account_info_data(&key);
         create_account_info(&key, true, true, lamports, data, owner, true);
     }
     account_manager::clear();
     let pidx: usize = (tx_instruction.program_id_index).into();
-    let program_id: &Pubkey = &tx.message.account_keys[pidx];
-    for (i, key) in tx.message.account_keys.iter().enumerate() {
-        let (data, lamports, owner) = load_account_info_data(&key);
+    let program_id: &Pubkey = &account_keys[pidx];
+    let writable: Vec<bool> = (0..account_keys.len())
+        .map(|i| tx.is_writable(i, &loaded_addresses))
+        .collect();
+    let signers: Vec<bool> = account_keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| tx.signatures.len() > i && check_signer_by_sender(key, &sender_bytes))
+        .collect();
+    for (i, key) in account_keys.iter().enumerate() {
+        let (data, lamports, owner) = if sysvar::instructions::check_id(key) {
+            let mut account =
+                sysvar_accounts::load_instructions_account(&account_keys, instructions, &writable, &signers);
+            sysvar_accounts::set_current_instruction(&mut account.0, instruction_index);
+            account
+        } else {
+            load_account_info_data(&key)
+        };
         println!(
             "loading account with key = {:?}; data.len() = {}; program_id = {:?}",
             &key,
             data.len(),
             program_id
         );
-        let mut is_signer = false;
-        if tx.signatures.len() > i {
-            is_signer = check_signer_by_sender(&key, &sender_bytes);
-        }
-        let is_writable = true; // todo
+        let is_signer = signers[i];
+        let is_writable = writable[i];
         let executable = true;
         let account_info = create_account_info(
             &key,
@@ -351,7 +540,7 @@ pub fn parse_processor_args<'a>(
         );
         accounts.push(account_info.to_owned());
     }
-    for (i, key) in tx.message.account_keys.iter().enumerate() {
+    for (i, key) in account_keys.iter().enumerate() {
         assert_eq!(key, accounts[i].key);
     }
 
@@ -378,7 +567,7 @@ pub fn parse_processor_args<'a>(
         "last_instruction = {}; {}/{}",
         last_instruction,
         instruction_index + 1,
-        tx.message.instructions.len()
+        instructions.len()
     );
     (
         program_id.to_owned(),
@@ -388,9 +577,71 @@ pub fn parse_processor_args<'a>(
     )
 }
 
+/// Lamports, data and owner of the readonly accounts, taken before the
+/// program runs so `check_readonly_accounts` can tell if it changed them.
+pub fn snapshot_readonly_accounts(accounts: &[AccountInfo]) -> Vec<(Pubkey, u64, Vec<u8>, Pubkey)> {
+    accounts
+        .iter()
+        .filter(|acc| !acc.is_writable)
+        .map(|acc| (*acc.key, acc.lamports(), acc.data.borrow().to_vec(), *acc.owner))
+        .collect()
+}
+
+/// Rejects the instruction if the program modified a readonly account.
+pub fn check_readonly_accounts(accounts: &[AccountInfo], snapshot: &[(Pubkey, u64, Vec<u8>, Pubkey)]) -> ProgramResult {
+    let readonly_accounts = accounts.iter().filter(|acc| !acc.is_writable);
+    for (acc, (key, lamports, data, owner)) in readonly_accounts.zip(snapshot.iter()) {
+        let data_changed = acc.data.borrow()[..] != data[..];
+        if acc.lamports() != *lamports || data_changed || acc.owner != owner {
+            println!("readonly account {:?} was modified", key);
+            return Err(ProgramError::InvalidAccountData);
+        }
+    }
+    Ok(())
+}
+
+/// Data size and lamports of the writable accounts, taken before the program
+/// runs so `check_rent_exemption` can tell the new and resized ones.
+pub fn snapshot_rent_state(accounts: &[AccountInfo]) -> Vec<(Pubkey, usize, u64)> {
+    accounts
+        .iter()
+        .filter(|acc| acc.is_writable)
+        .map(|acc| (*acc.key, acc.data_len(), acc.lamports()))
+        .collect()
+}
+
+/// When `rent_enforced`, rejects the instruction if it left a new or resized
+/// account below the rent exempt minimum, unless it has no lamports at all.
+pub fn check_rent_exemption(accounts: &[AccountInfo], snapshot: &[(Pubkey, usize, u64)]) -> ProgramResult {
+    if !rent_enforced() {
+        return Ok(());
+    }
+    let rent = current_rent();
+    let writable_accounts = accounts.iter().filter(|acc| acc.is_writable);
+    for (acc, (key, data_len, lamports)) in writable_accounts.zip(snapshot.iter()) {
+        // the data of the AccountInfo may have been consumed by a serialization
+        let new_data_len = account_manager::get_resized(key).map_or(*data_len, |data| data.len());
+        let created = *lamports == 0;
+        if acc.lamports() == 0 || (!created && new_data_len == *data_len) {
+            continue;
+        }
+        if !rent.is_exempt(acc.lamports(), new_data_len) {
+            println!(
+                "account {:?} is not rent exempt: {} lamports, {} required",
+                key,
+                acc.lamports(),
+                rent.minimum_balance(new_data_len)
+            );
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+    }
+    Ok(())
+}
+
+/// Saves the writable accounts, readonly ones are never persisted.
 pub fn persist_accounts(accounts: &[AccountInfo], delete: bool) {
-    let account_manager = create_account_manager();
-    for acc in accounts.iter() {
+    let mut writes: Vec<AccountWrite> = vec![];
+    for acc in accounts.iter().filter(|acc| acc.is_writable && !sysvar_accounts::is_sysvar(acc.key)) {
         let data = acc.data.borrow_mut();
         let lamports: u64 = **acc.lamports.borrow_mut();
         let account_file_data = AccountFileData {
@@ -400,36 +651,35 @@ pub fn persist_accounts(accounts: &[AccountInfo], delete: bool) {
         };
         println!("should delete = {}", delete);
         if delete && lamports <= 0 {
-            account_manager.delete_account(&acc.key).unwrap();
             println!("! deleted = {:?}", acc.key);
+            writes.push((*acc.key, None));
         } else {
-            account_manager
-                .write_account(&acc.key, &account_file_data)
-                .unwrap();
             println!("   saved = {:?};", acc.key);
             println!("     owner = {:?}", acc.owner.to_string());
+            writes.push((*acc.key, Some(account_file_data)));
         }
     }
+    create_account_manager()
+        .commit_accounts(&writes)
+        .expect("failed to persist the accounts");
 }
 
+/// Runs the program and persists its accounts, unless it returns an error.
 pub fn call_smart_contract_base64(
     payload: &str,
     msg_sender: &str,
     instruction_index: usize,
     solana_program_entrypoint: fn(&Pubkey, &[AccountInfo], &[u8]) -> ProgramResult,
-) {
+) -> ProgramResult {
     let (program_id, accounts, data, last_instruction) =
         parse_processor_args(payload, msg_sender, instruction_index);
-    let resp = solana_program_entrypoint(&program_id, &accounts, &data);
-    resp.unwrap();
-    // match resp {
-    //     Ok(_) => {
-    //         println!("Success!");
-    //     }
-    //     Err(_) => {
-    //         println!("Error: Something is not right! Handle any errors plz");
-    //     }
-    // }
+    let resp = run_program(solana_program_entrypoint, &program_id, &accounts, &data);
+    log_collector::report_logs();
+    if let Err(error) = resp {
+        report_error(Some(&program_id), Some(instruction_index), &error);
+        return Err(error);
+    }
     println!("Persist {:?} accounts...", program_id);
     persist_accounts(&accounts, last_instruction);
+    Ok(())
 }
@@ -0,0 +1,249 @@
+/// Built-in Address Lookup Table program
+///
+/// Lookup tables are persisted like any other account (see `AccountFileData`),
+/// so the executor can expand the `address_table_lookups` of v0 messages.
+/// The slots of the tables come from the input metadata, the instructions
+/// fail without it: every input would be at slot 1, where the extended
+/// addresses never become active and the tables never finish deactivating.
+use solana_program::{
+    account_info::AccountInfo,
+    address_lookup_table::{
+        instruction::ProgramInstruction,
+        program::id,
+        state::{AddressLookupTable, LookupTableMeta, LookupTableStatus, LOOKUP_TABLE_MAX_ADDRESSES, LOOKUP_TABLE_META_SIZE},
+    },
+    clock::Slot,
+    entrypoint::ProgramResult,
+    hash::Hash,
+    instruction::InstructionError,
+    message::{
+        v0::{LoadedAddresses, MessageAddressTableLookup},
+        VersionedMessage,
+    },
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    slot_hashes::{SlotHashes, MAX_ENTRIES},
+};
+
+pub use solana_program::address_lookup_table::program::check_id;
+
+use crate::{
+    account_manager,
+    adapter::{self, load_account_info_data},
+    cartesi_stub, log_collector, owner_manager, system_program,
+};
+
+/// There is no bank here, so the recent slots are the ones before the current
+/// slot, limited to the same amount of entries of the `SlotHashes` sysvar.
+pub(crate) fn recent_slot_hashes(current_slot: Slot) -> SlotHashes {
+    let oldest = current_slot.saturating_sub(MAX_ENTRIES as Slot);
+    let slot_hashes: Vec<(Slot, Hash)> = (oldest..current_slot)
+        .map(|slot| (slot, Hash::default()))
+        .collect();
+    SlotHashes::new(&slot_hashes)
+}
+
+fn to_program_error(error: InstructionError) -> ProgramError {
+    ProgramError::try_from(error).unwrap_or(ProgramError::InvalidAccountData)
+}
+
+fn read_table(table_info: &AccountInfo) -> Result<(LookupTableMeta, Vec<Pubkey>), ProgramError> {
+    if table_info.owner != &id() {
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    let data = table_info.try_borrow_data()?;
+    let table = AddressLookupTable::deserialize(&data).map_err(to_program_error)?;
+    Ok((table.meta, table.addresses.to_vec()))
+}
+
+fn write_table(table_info: &AccountInfo, meta: LookupTableMeta, addresses: &[Pubkey]) -> ProgramResult {
+    let mut data = vec![0u8; LOOKUP_TABLE_META_SIZE];
+    AddressLookupTable::overwrite_meta_data(&mut data, meta).map_err(to_program_error)?;
+    for address in addresses.iter() {
+        data.extend_from_slice(address.as_ref());
+    }
+    account_manager::set_data(table_info, data);
+    Ok(())
+}
+
+fn check_authority(meta: &LookupTableMeta, authority_info: &AccountInfo) -> ProgramResult {
+    match meta.authority {
+        // frozen tables are immutable
+        None => Err(ProgramError::InvalidArgument),
+        Some(authority) if authority != *authority_info.key => Err(ProgramError::InvalidArgument),
+        Some(_) if !authority_info.is_signer => Err(ProgramError::MissingRequiredSignature),
+        Some(_) => Ok(()),
+    }
+}
+
+/// The payer pays through the System Program, so it must be one of its accounts.
+fn fund_rent_exemption(payer_info: &AccountInfo, table_info: &AccountInfo, data_len: usize) -> ProgramResult {
+    let required = cartesi_stub::current_rent().minimum_balance(data_len).max(1);
+    let missing = required.saturating_sub(table_info.lamports());
+    if missing == 0 {
+        return Ok(());
+    }
+    system_program::process_transfer(payer_info, table_info, missing)
+}
+
+pub fn process_instruction(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    if adapter::get_input_metadata().is_none() {
+        log_collector::log("The Address Lookup Table program needs the input metadata for its slots");
+        return Err(ProgramError::InvalidArgument);
+    }
+    let instruction: ProgramInstruction =
+        bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+    match instruction {
+        ProgramInstruction::CreateLookupTable { recent_slot, bump_seed } => {
+            create_lookup_table(program_id, accounts, recent_slot, bump_seed)
+        }
+        ProgramInstruction::FreezeLookupTable => freeze_lookup_table(accounts),
+        ProgramInstruction::ExtendLookupTable { new_addresses } => extend_lookup_table(accounts, new_addresses),
+        ProgramInstruction::DeactivateLookupTable => deactivate_lookup_table(accounts),
+        ProgramInstruction::CloseLookupTable => close_lookup_table(accounts),
+    }
+}
+
+fn create_lookup_table(program_id: &Pubkey, accounts: &[AccountInfo], recent_slot: Slot, bump_seed: u8) -> ProgramResult {
+    let [table_info, authority_info, payer_info, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    if !table_info.data_is_empty() || table_info.owner == program_id {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+    if !payer_info.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    let current_slot = cartesi_stub::current_clock().slot;
+    if recent_slot_hashes(current_slot).get(&recent_slot).is_none() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let derived_table_key = Pubkey::create_program_address(
+        &[authority_info.key.as_ref(), &recent_slot.to_le_bytes(), &[bump_seed]],
+        program_id,
+    )?;
+    if table_info.key != &derived_table_key {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    fund_rent_exemption(payer_info, table_info, LOOKUP_TABLE_META_SIZE)?;
+    write_table(table_info, LookupTableMeta::new(*authority_info.key), &[])?;
+    owner_manager::change_owner(*table_info.key, *program_id);
+    Ok(())
+}
+
+fn freeze_lookup_table(accounts: &[AccountInfo]) -> ProgramResult {
+    let [table_info, authority_info, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let (mut meta, addresses) = read_table(table_info)?;
+    check_authority(&meta, authority_info)?;
+    if meta.deactivation_slot != Slot::MAX {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if addresses.is_empty() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    meta.authority = None;
+    write_table(table_info, meta, &addresses)
+}
+
+fn extend_lookup_table(accounts: &[AccountInfo], new_addresses: Vec<Pubkey>) -> ProgramResult {
+    let [table_info, authority_info, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let (mut meta, mut addresses) = read_table(table_info)?;
+    check_authority(&meta, authority_info)?;
+    if meta.deactivation_slot != Slot::MAX {
+        return Err(ProgramError::InvalidArgument);
+    }
+    if new_addresses.is_empty() || addresses.len() + new_addresses.len() > LOOKUP_TABLE_MAX_ADDRESSES {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    let current_slot = cartesi_stub::current_clock().slot;
+    if current_slot != meta.last_extended_slot {
+        meta.last_extended_slot = current_slot;
+        meta.last_extended_slot_start_index = addresses.len() as u8;
+    }
+    addresses.extend(new_addresses);
+    write_table(table_info, meta, &addresses)?;
+
+    let data_len = table_info.data_len();
+    match accounts.get(2) {
+        Some(payer_info) => fund_rent_exemption(payer_info, table_info, data_len),
+        None if table_info.lamports() >= cartesi_stub::current_rent().minimum_balance(data_len) => Ok(()),
+        None => Err(ProgramError::NotEnoughAccountKeys),
+    }
+}
+
+fn deactivate_lookup_table(accounts: &[AccountInfo]) -> ProgramResult {
+    let [table_info, authority_info, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let (mut meta, addresses) = read_table(table_info)?;
+    check_authority(&meta, authority_info)?;
+    if meta.deactivation_slot != Slot::MAX {
+        return Err(ProgramError::InvalidArgument);
+    }
+    meta.deactivation_slot = cartesi_stub::current_clock().slot;
+    write_table(table_info, meta, &addresses)
+}
+
+fn close_lookup_table(accounts: &[AccountInfo]) -> ProgramResult {
+    let [table_info, authority_info, recipient_info, ..] = accounts else {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    };
+    let (meta, _) = read_table(table_info)?;
+    check_authority(&meta, authority_info)?;
+    if recipient_info.key == table_info.key {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let current_slot = cartesi_stub::current_clock().slot;
+    if meta.status(current_slot, &recent_slot_hashes(current_slot)) != LookupTableStatus::Deactivated {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let lamports = table_info.lamports();
+    **recipient_info.try_borrow_mut_lamports()? += lamports;
+    **table_info.try_borrow_mut_lamports()? = 0;
+    account_manager::set_data_size(table_info, 0);
+    owner_manager::change_owner(*table_info.key, system_program::id());
+    Ok(())
+}
+
+/// Loads the addresses referenced by the lookups from the persisted lookup tables.
+pub fn load_addresses(lookups: &[MessageAddressTableLookup]) -> Result<LoadedAddresses, ProgramError> {
+    let current_slot = cartesi_stub::current_clock().slot;
+    let slot_hashes = recent_slot_hashes(current_slot);
+    let mut loaded = LoadedAddresses::default();
+    for lookup in lookups.iter() {
+        let (data, _, owner) = load_account_info_data(&lookup.account_key);
+        if !check_id(&owner) {
+            return Err(ProgramError::InvalidAccountOwner);
+        }
+        let table = AddressLookupTable::deserialize(&data).map_err(to_program_error)?;
+        let mut writable = table
+            .lookup(current_slot, &lookup.writable_indexes, &slot_hashes)
+            .map_err(|_| ProgramError::InvalidArgument)?;
+        let mut readonly = table
+            .lookup(current_slot, &lookup.readonly_indexes, &slot_hashes)
+            .map_err(|_| ProgramError::InvalidArgument)?;
+        loaded.writable.append(&mut writable);
+        loaded.readonly.append(&mut readonly);
+    }
+    Ok(loaded)
+}
+
+/// The account list indexed by the message instructions: the static keys
+/// followed by the writable and then the readonly looked up addresses.
+pub fn load_account_keys(message: &VersionedMessage) -> Result<(Vec<Pubkey>, LoadedAddresses), ProgramError> {
+    let loaded = match message.address_table_lookups() {
+        Some(lookups) => load_addresses(lookups)?,
+        None => LoadedAddresses::default(),
+    };
+    let mut account_keys = message.static_account_keys().to_vec();
+    account_keys.extend(loaded.writable.iter());
+    account_keys.extend(loaded.readonly.iter());
+    Ok((account_keys, loaded))
+}
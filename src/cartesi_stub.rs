@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use solana_program::{self, pubkey::Pubkey, stake_history::Epoch};
 
+use crate::{compute_budget, log_collector};
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct AccountInfoSerialize {
     pub key: Pubkey,
@@ -23,6 +25,7 @@ fn execute_spawn(program_id: String) -> std::process::Child {
 
     let exec = std::process::Command::new(&path)
         .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
         .spawn();
 
     match exec {
@@ -36,32 +39,106 @@ fn execute_spawn(program_id: String) -> std::process::Child {
     }
 }
 
+/// Runs `instruction` in its program spawned from `SOLANA_BIN_PATH`, called by
+/// `caller_program_id` at `stack_height`, see `executor::Executor::handle_cpi_call`.
+/// The accounts it sends back in its `CPI Response` are applied to `account_infos`.
 #[cfg(not(target_arch = "bpf"))]
-fn refresh_accounts(accounts: &[solana_program::account_info::AccountInfo]) -> Result<(), solana_program::program_error::ProgramError> {
-    let account_manager = crate::account_manager::create_account_manager();
-    println!("refresh_accounts {:?}", account_manager);
-
-    for account_info in accounts.iter() {
-        let account_data = account_manager.read_account(account_info.key);
-
-        match account_data {
-            Ok(account_data) => {
-                println!(
-                    "  refresh key {:?}; data.len() = {}",
-                    account_info.key,
-                    account_data.data.len()
-                );
-                crate::account_manager::set_data(account_info, account_data.data);
-                **account_info.try_borrow_mut_lamports()? = account_data.lamports;
-                if account_info.owner != &account_data.owner {
-                    crate::owner_manager::change_owner(
-                        account_info.key.clone(),
-                        account_data.owner.to_owned(),
-                    );
-                }
+pub fn invoke_spawned(
+    caller_program_id: &Pubkey,
+    instruction: &solana_program::instruction::Instruction,
+    account_infos: &[solana_program::account_info::AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    stack_height: u64,
+) -> solana_program::entrypoint::ProgramResult {
+    let account_infos_serialized: Vec<AccountInfoSerialize> = account_infos
+        .iter()
+        .map(|account| AccountInfoSerialize {
+            key: account.key.to_owned(),
+            is_signer: account.is_signer,
+            is_writable: account.is_writable,
+            owner: account.owner.to_owned(),
+            lamports: account.lamports.borrow_mut().to_owned(),
+
+            // @todo: verify the serialized data by borsh
+            data: account.data.borrow_mut().to_vec(),
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+        })
+        .collect();
+
+    let lines = [
+        "Header: CPI".to_string(),
+        base64::encode(bincode::serialize(instruction).unwrap()),
+        base64::encode(bincode::serialize(&account_infos_serialized).unwrap()),
+        base64::encode(bincode::serialize(&signers_seeds).unwrap()),
+        crate::adapter::get_timestamp_line().to_string(),
+        base64::encode(bincode::serialize(caller_program_id).unwrap()),
+        compute_budget::remaining_compute_units().to_string(),
+        stack_height.to_string(),
+    ];
+    let input = lines.join("\n") + "\n";
+
+    let mut child = execute_spawn(instruction.program_id.to_string());
+    // on their own threads, so a program that hangs can't block its caller
+    let mut child_stdin = child.stdin.take().unwrap();
+    let writer = std::thread::spawn(move || std::io::Write::write_all(&mut child_stdin, input.as_bytes()));
+    let mut child_stdout = child.stdout.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut stdout = vec![];
+        std::io::Read::read_to_end(&mut child_stdout, &mut stdout).map(|_| stdout)
+    });
+    let status = match wait_timeout(&mut child, cpi_timeout())? {
+        Some(status) => status,
+        None => {
+            child.kill()?;
+            child.wait()?;
+            log_collector::log(&format!("Program {} timed out", instruction.program_id));
+            return Err(compute_budget::COMPUTATIONAL_BUDGET_EXCEEDED);
+        }
+    };
+    let stdout = reader.join().unwrap()?;
+    writer.join().unwrap()?;
+    println!("output: {:?}", status);
+    let mut cpi_response = None;
+    for line in String::from_utf8_lossy(&stdout).lines() {
+        match crate::cpi::parse_cpi_response(line) {
+            Some(response) => {
+                log_collector::append_logs(&response.logs);
+                crate::cpi::apply_cpi_response_return_data(&response);
+                cpi_response = Some(response);
             }
-            Err(_) => {
-                println!("refresh_accounts account not found {:?}", account_info.key);
+            None => println!("{}", line),
+        }
+    }
+    // the units of the called program are charged to the caller
+    if let Some(response) = &cpi_response {
+        compute_budget::consume(response.compute_units_consumed)?;
+    }
+
+    // the error of the called program, as it returned it
+    if let Some(error) = cpi_response.as_ref().and_then(|response| response.error) {
+        let error = solana_program::program_error::ProgramError::from(error);
+        println!("Program failed: {}", error);
+        return Err(error);
+    }
+
+    let exit_code = status.code();
+
+    match exit_code {
+        None => {
+            println!("Program failed to run");
+            return Err(solana_program::program_error::ProgramError::Custom(1));
+        }
+        Some(code) => {
+            if code == 0 {
+                if let Some(response) = &cpi_response {
+                    crate::cpi::apply_cpi_response_accounts(response, account_infos)?;
+                }
+
+                println!("Program exited with success code");
+            } else {
+                println!("Program exited with error code: {}", code);
+                return Err(solana_program::program_error::ProgramError::Custom(1));
             }
         }
     }
@@ -69,149 +146,258 @@ fn refresh_accounts(accounts: &[solana_program::account_info::AccountInfo]) -> R
     Ok(())
 }
 
-pub struct CartesiStubs {
-    pub program_id: Pubkey,
+/// How long a spawned program may run, `SOLANA_CPI_TIMEOUT` seconds (60 by
+/// default). Programs run natively, so a loop without syscalls never runs out
+/// of compute units.
+#[cfg(not(target_arch = "bpf"))]
+fn cpi_timeout() -> std::time::Duration {
+    std::time::Duration::from_secs(number_var("SOLANA_CPI_TIMEOUT").unwrap_or(60))
 }
 
+/// Waits for the child up to `timeout`, `None` when it is still running.
 #[cfg(not(target_arch = "bpf"))]
-impl solana_program::program_stubs::SyscallStubs for CartesiStubs {
-    fn sol_set_return_data(&self, data: &[u8]) {
-        let path = std::path::Path::new(&crate::adapter::get_binary_base_path()).join("return_data.out");
+fn wait_timeout(
+    child: &mut std::process::Child,
+    timeout: std::time::Duration,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if std::time::Instant::now() >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
 
-        let program_id = self.program_id.to_string();
+/// The clock seen by programs and by the built-in programs of this crate.
+/// The slot comes from the metadata of the input and the epochs from the
+/// slot, see `current_epoch_schedule`. Inputs sent with the timestamp alone
+/// are always at slot 1 of the epoch 1.
+pub fn current_clock() -> solana_program::clock::Clock {
+    let timestamp = crate::adapter::get_timestamp();
+    let (slot, epoch, leader_schedule_epoch) = match crate::adapter::get_input_metadata() {
+        Some(metadata) => {
+            let slot = metadata_slot(&metadata).expect("input metadata checked by set_timestamp_line");
+            let epoch_schedule = current_epoch_schedule();
+            (slot, epoch_schedule.get_epoch(slot), epoch_schedule.get_leader_schedule_epoch(slot))
+        }
+        None => (1, 1, 1),
+    };
+    solana_program::clock::Clock {
+        slot,
+        epoch_start_timestamp: timestamp,
+        epoch,
+        leader_schedule_epoch,
+        unix_timestamp: timestamp,
+    }
+}
 
-        let data = base64::encode(data);
+/// How the slot is derived from the input metadata, set by `SOLANA_CLOCK_SLOT`.
+enum SlotMapping {
+    /// The input index plus 1, slot 0 is left for the genesis.
+    InputIndex,
+    /// For input indexes that restart every epoch: the epoch index in the
+    /// high 32 bits and the input index plus 1 in the low ones.
+    EpochAndInputIndex,
+}
 
-        std::fs::write(&path, format!("{}\n{}", program_id, data))
-            .expect(&format!("failed to write return data file: [{:?}]", &path));
+fn slot_mapping() -> SlotMapping {
+    match std::env::var("SOLANA_CLOCK_SLOT").as_deref() {
+        Ok("input_index") | Err(_) => SlotMapping::InputIndex,
+        Ok("epoch_and_input_index") => SlotMapping::EpochAndInputIndex,
+        Ok(other) => panic!("Unknown SOLANA_CLOCK_SLOT: {}", other),
     }
+}
 
-    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
-        let path = std::path::Path::new(&crate::adapter::get_binary_base_path()).join("return_data.out");
+/// The input index is strictly increasing, so is the slot. `None` when the
+/// metadata doesn't fit in a slot: with `epoch_and_input_index` the epoch
+/// index must fit in 32 bits, and so must the input index plus 1.
+pub fn metadata_slot(metadata: &crate::adapter::InputMetadata) -> Option<solana_program::clock::Slot> {
+    let slot_in_epoch = metadata.input_index.checked_add(1)?;
+    match slot_mapping() {
+        SlotMapping::InputIndex => Some(slot_in_epoch),
+        SlotMapping::EpochAndInputIndex if slot_in_epoch > u32::MAX as u64 => None,
+        SlotMapping::EpochAndInputIndex if metadata.epoch_index > u32::MAX as u64 => None,
+        SlotMapping::EpochAndInputIndex => Some((metadata.epoch_index << 32) | slot_in_epoch),
+    }
+}
 
-        if !path.exists() {
-            return None;
+/// The epochs of the slots of `current_clock`, without warmup. With
+/// `epoch_and_input_index` an epoch has 2^32 slots, so it is the epoch index
+/// of the input, otherwise it has `SOLANA_SLOTS_PER_EPOCH` slots (432000 by default).
+pub fn current_epoch_schedule() -> solana_program::epoch_schedule::EpochSchedule {
+    let slots_per_epoch = match slot_mapping() {
+        SlotMapping::InputIndex => {
+            number_var("SOLANA_SLOTS_PER_EPOCH").unwrap_or(solana_program::epoch_schedule::DEFAULT_SLOTS_PER_EPOCH)
         }
+        SlotMapping::EpochAndInputIndex => 1 << 32,
+    };
+    solana_program::epoch_schedule::EpochSchedule::custom(slots_per_epoch, slots_per_epoch, false)
+}
 
-        let bundle = std::fs::read_to_string(path).expect("failed to read return data file");
+/// The rent parameters seen by programs and by the built-in programs of this crate,
+/// the defaults of Solana unless `SOLANA_RENT_LAMPORTS_PER_BYTE_YEAR`,
+/// `SOLANA_RENT_EXEMPTION_THRESHOLD` or `SOLANA_RENT_BURN_PERCENT` are set.
+pub fn current_rent() -> solana_program::rent::Rent {
+    let default = solana_program::rent::Rent::default();
+    solana_program::rent::Rent {
+        lamports_per_byte_year: number_var("SOLANA_RENT_LAMPORTS_PER_BYTE_YEAR").unwrap_or(default.lamports_per_byte_year),
+        exemption_threshold: number_var("SOLANA_RENT_EXEMPTION_THRESHOLD").unwrap_or(default.exemption_threshold),
+        burn_percent: number_var("SOLANA_RENT_BURN_PERCENT").unwrap_or(default.burn_percent),
+    }
+}
 
-        let mut bundle = bundle.split("\n");
+fn number_var<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    Some(value.trim().parse().unwrap_or_else(|_| panic!("{} is not a valid number", name)))
+}
 
-        let program_id = bundle.next().unwrap();
-        let program_id = <Pubkey as std::str::FromStr>::from_str(&program_id).unwrap();
+/// With `SOLANA_RENT_ENFORCE=true` the new and resized accounts must be rent exempt.
+pub fn rent_enforced() -> bool {
+    std::env::var("SOLANA_RENT_ENFORCE").is_ok_and(|value| value == "true" || value == "1")
+}
 
-        let data = bundle.next().unwrap();
-        let data = base64::decode(data).unwrap();
+pub struct CartesiStubs {
+    pub program_id: Pubkey,
+}
 
-        Some((program_id, data))
+impl CartesiStubs {
+    /// The program making the syscall, which is a registered one while it
+    /// runs in process, see `cpi::register_program`.
+    fn current_program_id(&self) -> Pubkey {
+        crate::cpi::get_invoked_program().unwrap_or(self.program_id)
     }
+}
 
-    fn sol_invoke_signed(
-        &self,
-        instruction: &solana_program::instruction::Instruction,
-        account_infos: &[solana_program::account_info::AccountInfo], // chaves publicas
-        signers_seeds: &[&[&[u8]]],
-    ) -> Result<(), solana_program::program_error::ProgramError> {
-        crate::cpi::check_signature(&self.program_id, instruction, signers_seeds);
-
-        let mut child = execute_spawn(instruction.program_id.to_string());
-        let child_stdin = child.stdin.as_mut().unwrap();
-
-        let instruction = bincode::serialize(&instruction).unwrap();
-        let instruction = base64::encode(&instruction);
-
-        let account_infos_serialized: Vec<AccountInfoSerialize> = account_infos
-            .into_iter()
-            .map(|account| AccountInfoSerialize {
-                key: account.key.to_owned(),
-                is_signer: account.is_signer,
-                is_writable: account.is_writable,
-                owner: account.owner.to_owned(),
-                lamports: account.lamports.borrow_mut().to_owned(),
-
-                // @todo: verify the serialized data by borsh
-                data: account.data.borrow_mut().to_vec(),
-                executable: account.executable,
-                rent_epoch: account.rent_epoch,
-            })
-            .collect();
+#[cfg(not(target_arch = "bpf"))]
+impl solana_program::program_stubs::SyscallStubs for CartesiStubs {
+    fn sol_log(&self, message: &str) {
+        compute_budget::charge(compute_budget::SYSCALL_BASE_COST.max(message.len() as u64));
+        log_collector::program_log(message);
+    }
 
-        let account_infos_serialized = bincode::serialize(&account_infos_serialized).unwrap();
-        let account_infos_serialized = base64::encode(&account_infos_serialized);
-        let program_id_serialized = bincode::serialize(&self.program_id).unwrap();
-        let program_id_serialized = base64::encode(program_id_serialized);
-        let signers_seeds = bincode::serialize(&signers_seeds).unwrap();
-        let signers_seeds = base64::encode(&signers_seeds);
+    fn sol_log_data(&self, fields: &[&[u8]]) {
+        let bytes: usize = fields.iter().map(|field| field.len()).sum();
+        compute_budget::charge(compute_budget::SYSCALL_BASE_COST + bytes as u64);
+        log_collector::program_data(fields);
+    }
 
-        write_all(child_stdin, b"Header: CPI")?;
-        write_all(child_stdin, b"\n")?;
+    fn sol_log_compute_units(&self) {
+        compute_budget::charge(compute_budget::SYSCALL_BASE_COST);
+        log_collector::log(&format!(
+            "Program consumption: {} units remaining",
+            compute_budget::remaining_compute_units()
+        ));
+    }
 
-        write_all(child_stdin, instruction.as_bytes())?;
-        write_all(child_stdin, b"\n")?;
-        write_all(child_stdin, account_infos_serialized.as_bytes())?;
-        write_all(child_stdin, b"\n")?;
-        write_all(child_stdin, signers_seeds.as_bytes())?;
-        write_all(child_stdin, b"\n")?;
+    fn sol_get_stack_height(&self) -> u64 {
+        crate::cpi::get_stack_height()
+    }
 
-        write_all(child_stdin, crate::adapter::get_timestamp().to_string().as_bytes())?;
+    fn sol_remaining_compute_units(&self) -> u64 {
+        compute_budget::charge(compute_budget::SYSCALL_BASE_COST);
+        compute_budget::remaining_compute_units()
+    }
 
-        write_all(child_stdin, b"\n")?;
+    fn sol_set_return_data(&self, data: &[u8]) {
+        compute_budget::charge(compute_budget::bytes_cost(data.len()));
+        crate::cpi::set_return_data(&self.current_program_id(), data);
+    }
 
-        write_all(child_stdin, program_id_serialized.as_bytes())?;
-        write_all(child_stdin, b"\n")?;
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        let return_data = crate::cpi::get_return_data();
+        let bytes = return_data.as_ref().map_or(0, |(_, data)| data.len());
+        compute_budget::charge(compute_budget::bytes_cost(bytes));
+        return_data
+    }
 
-        drop(child_stdin);
+    fn sol_invoke_signed(
+        &self,
+        instruction: &solana_program::instruction::Instruction,
+        account_infos: &[solana_program::account_info::AccountInfo], // chaves publicas
+        signers_seeds: &[&[&[u8]]],
+    ) -> Result<(), solana_program::program_error::ProgramError> {
+        let program_id = self.current_program_id();
+        compute_budget::consume(compute_budget::INVOKE_UNITS + instruction.data.len() as u64 / compute_budget::CPI_BYTES_PER_UNIT)?;
+        if let Some(entrypoint) = crate::cpi::get_registered_program(&instruction.program_id) {
+            return crate::cpi::invoke_registered(entrypoint, &program_id, instruction, account_infos, signers_seeds);
+        }
+        let caller_signers: Vec<Pubkey> = account_infos
+            .iter()
+            .filter(|account| account.is_signer)
+            .map(|account| *account.key)
+            .collect();
+        crate::cpi::check_signature(&program_id, instruction, &caller_signers, signers_seeds)?;
+        crate::cpi::reset_return_data(&instruction.program_id);
 
-        let output = child.wait_with_output()?;
-        println!("output: {:?}", output);
+        invoke_spawned(&program_id, instruction, account_infos, signers_seeds, crate::cpi::get_stack_height() + 1)
+    }
 
-        let exit_code = output.status.code();
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::rent::Rent>() as u64) {
+            return error.into();
+        }
+        unsafe {
+            *(var_addr as *mut _ as *mut solana_program::rent::Rent) = current_rent();
+        }
+        solana_program::entrypoint::SUCCESS
+    }
 
-        match exit_code {
-            None => {
-                println!("Program failed to run");
-                return Err(solana_program::program_error::ProgramError::Custom(1));
-            }
-            Some(code) => {
-                if code == 0 {
-                    refresh_accounts(account_infos)?;
-
-                    println!("Program exited with success code");
-                } else {
-                    println!("Program exited with error code: {}", code);
-                    return Err(solana_program::program_error::ProgramError::Custom(1));
-                }
-            }
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::clock::Clock>() as u64) {
+            return error.into();
+        }
+        unsafe {
+            *(var_addr as *mut _ as *mut solana_program::clock::Clock) = current_clock();
         }
+        solana_program::entrypoint::SUCCESS
+    }
 
-        Ok(())
+    fn sol_get_epoch_schedule_sysvar(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::epoch_schedule::EpochSchedule>() as u64) {
+            return error.into();
+        }
+        unsafe {
+            *(var_addr as *mut _ as *mut solana_program::epoch_schedule::EpochSchedule) = current_epoch_schedule();
+        }
+        solana_program::entrypoint::SUCCESS
     }
 
-    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+    /// There are no transaction fees here.
+    #[allow(deprecated)]
+    fn sol_get_fees_sysvar(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::sysvar::fees::Fees>() as u64) {
+            return error.into();
+        }
         unsafe {
-            *(var_addr as *mut _ as *mut solana_program::rent::Rent) =
-                solana_program::rent::Rent::default();
+            *(var_addr as *mut _ as *mut solana_program::sysvar::fees::Fees) = solana_program::sysvar::fees::Fees::default();
         }
         solana_program::entrypoint::SUCCESS
     }
 
-    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+    /// Nor staking rewards.
+    fn sol_get_epoch_rewards_sysvar(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::epoch_rewards::EpochRewards>() as u64) {
+            return error.into();
+        }
         unsafe {
-            *(var_addr as *mut _ as *mut solana_program::clock::Clock) =
-                solana_program::clock::Clock {
-                    slot: 1,
-                    epoch_start_timestamp: crate::adapter::get_timestamp(),
-                    epoch: 1,
-                    leader_schedule_epoch: 1,
-                    unix_timestamp: crate::adapter::get_timestamp(),
-                };
+            *(var_addr as *mut _ as *mut solana_program::epoch_rewards::EpochRewards) =
+                solana_program::epoch_rewards::EpochRewards::default();
         }
         solana_program::entrypoint::SUCCESS
     }
-}
 
-#[cfg(not(target_arch = "bpf"))]
-fn write_all(child_stdin: &mut std::process::ChildStdin, buf: &[u8]) -> Result<(), solana_program::program_error::ProgramError> {
-    std::io::Write::write_all(child_stdin, buf)?;
-    Ok(())
+    fn sol_get_last_restart_slot(&self, var_addr: *mut u8) -> u64 {
+        if let Err(error) = compute_budget::consume(compute_budget::SYSVAR_BASE_COST + std::mem::size_of::<solana_program::last_restart_slot::LastRestartSlot>() as u64) {
+            return error.into();
+        }
+        unsafe {
+            *(var_addr as *mut _ as *mut solana_program::last_restart_slot::LastRestartSlot) =
+                solana_program::last_restart_slot::LastRestartSlot::default();
+        }
+        solana_program::entrypoint::SUCCESS
+    }
 }
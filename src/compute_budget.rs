@@ -0,0 +1,185 @@
+/// Compute budget
+///
+/// Programs run natively here, so there are no BPF instructions to count: the
+/// units are charged by the syscalls, the CPIs and the built-in programs, with
+/// the costs of Solana, against the limit of the transaction set by the
+/// ComputeBudget instructions. Running out aborts the program with
+/// `COMPUTATIONAL_BUDGET_EXCEEDED`, whatever it returns after, like Solana
+/// aborts the transaction, so none of its accounts are persisted. A program
+/// looping without calling any syscall is only interrupted when it is spawned,
+/// see `cartesi_stub::invoke_spawned`.
+use serde::{Deserialize, Serialize};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, pubkey::Pubkey};
+use std::sync::Mutex;
+
+solana_program::declare_id!("ComputeBudget111111111111111111111111111111");
+
+pub const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+pub const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+
+pub const SYSCALL_BASE_COST: u64 = 100;
+pub const LOG_64_UNITS: u64 = 100;
+pub const SYSVAR_BASE_COST: u64 = 100;
+pub const INVOKE_UNITS: u64 = 1_000;
+pub const CPI_BYTES_PER_UNIT: u64 = 250;
+pub const COMPUTE_BUDGET_PROGRAM_UNITS: u64 = 150;
+pub const SYSTEM_PROGRAM_UNITS: u64 = 150;
+pub const ADDRESS_LOOKUP_TABLE_PROGRAM_UNITS: u64 = 750;
+
+/// The `ComputationalBudgetExceeded` error of Solana, an `InstructionError`
+/// that `ProgramError` can't express, so it takes the last custom code.
+pub const COMPUTATIONAL_BUDGET_EXCEEDED: ProgramError = ProgramError::Custom(u32::MAX);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComputeMeter {
+    pub limit: u64,
+    pub consumed: u64,
+    /// micro-lamports per unit, there are no fees here but programs may read it
+    pub unit_price: u64,
+}
+
+impl ComputeMeter {
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.consumed)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref COMPUTE_METER: Mutex<ComputeMeter> = Mutex::new(ComputeMeter {
+        limit: MAX_COMPUTE_UNIT_LIMIT,
+        consumed: 0,
+        unit_price: 0,
+    });
+}
+
+/// Starts the meter of a transaction, or of a CPI with what its caller has left.
+pub fn set_compute_budget(limit: u64, unit_price: u64) {
+    *COMPUTE_METER.lock().unwrap() = ComputeMeter {
+        limit,
+        consumed: 0,
+        unit_price,
+    };
+}
+
+/// The budget of a CPI, the line with the units its caller has left, which
+/// callers from before the compute budget don't send.
+pub fn set_compute_budget_line(line: &str) {
+    let limit = match line.trim() {
+        "" => MAX_COMPUTE_UNIT_LIMIT,
+        units => units.parse().expect("Compute units is not an integer"),
+    };
+    set_compute_budget(limit, 0);
+}
+
+pub fn get_compute_meter() -> ComputeMeter {
+    *COMPUTE_METER.lock().unwrap()
+}
+
+pub fn remaining_compute_units() -> u64 {
+    get_compute_meter().remaining()
+}
+
+/// Charges the units, the budget stays exceeded once it runs out.
+pub fn consume(units: u64) -> ProgramResult {
+    {
+        let mut meter = COMPUTE_METER.lock().unwrap();
+        meter.consumed = meter.consumed.saturating_add(units).min(meter.limit.saturating_add(1));
+    }
+    check_budget()
+}
+
+/// `COMPUTATIONAL_BUDGET_EXCEEDED` once the budget ran out.
+pub fn check_budget() -> ProgramResult {
+    let meter = get_compute_meter();
+    if meter.consumed > meter.limit {
+        println!("Computational budget exceeded: the limit is {} units", meter.limit);
+        return Err(COMPUTATIONAL_BUDGET_EXCEEDED);
+    }
+    Ok(())
+}
+
+/// What `abort` unwinds the program with, caught by `run_metered`.
+struct Aborted(ProgramError);
+
+/// Fails the program from a syscall that can't return an error, as it may
+/// never return otherwise.
+pub fn abort(error: ProgramError) -> ! {
+    std::panic::resume_unwind(Box::new(Aborted(error)))
+}
+
+/// Charges the units of a syscall that can't return an error, aborting the
+/// program when the budget runs out.
+pub fn charge(units: u64) {
+    if let Err(error) = consume(units) {
+        abort(error);
+    }
+}
+
+/// Runs a program, which fails with `COMPUTATIONAL_BUDGET_EXCEEDED` when it
+/// runs out of units, aborted or not, or with the error it was aborted with.
+/// Other panics go through.
+pub fn run_metered<F: FnOnce() -> ProgramResult>(program: F) -> ProgramResult {
+    let result = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(program)) {
+        Ok(result) => result,
+        Err(payload) => match payload.downcast::<Aborted>() {
+            Ok(aborted) => Err(aborted.0),
+            Err(payload) => std::panic::resume_unwind(payload),
+        },
+    };
+    check_budget().and(result)
+}
+
+/// The units of a syscall that handles `bytes` bytes.
+pub fn bytes_cost(bytes: usize) -> u64 {
+    SYSCALL_BASE_COST + bytes as u64 / CPI_BYTES_PER_UNIT
+}
+
+/// The limit and the price set by the ComputeBudget instructions of the
+/// transaction, by default 200k units per other instruction.
+pub fn compute_budget_from_instructions<'a>(
+    instructions: impl Iterator<Item = (&'a Pubkey, &'a [u8])>,
+) -> Result<(u64, u64), ProgramError> {
+    let mut unit_limit: Option<u64> = None;
+    let mut unit_price: Option<u64> = None;
+    let mut other_instructions = 0;
+    for (program_id, data) in instructions {
+        if !check_id(program_id) {
+            other_instructions += 1;
+            continue;
+        }
+        // the borsh encoding of ComputeBudgetInstruction
+        let (value, is_set) = match (data.first(), data.len()) {
+            // RequestHeapFrame and SetLoadedAccountsDataSizeLimit, there is nothing to limit
+            (Some(1), 5) | (Some(4), 5) => continue,
+            (Some(2), 5) => (u32::from_le_bytes(data[1..5].try_into().unwrap()) as u64, &mut unit_limit),
+            (Some(3), 9) => (u64::from_le_bytes(data[1..9].try_into().unwrap()), &mut unit_price),
+            _ => return Err(ProgramError::InvalidInstructionData),
+        };
+        if is_set.replace(value).is_some() {
+            println!("duplicate ComputeBudget instruction");
+            return Err(ProgramError::InvalidInstructionData);
+        }
+    }
+    let unit_limit = unit_limit.unwrap_or(DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT * other_instructions);
+    Ok((unit_limit.min(MAX_COMPUTE_UNIT_LIMIT), unit_price.unwrap_or(0)))
+}
+
+/// What is printed after each instruction, like the `consumed` log of Solana.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ComputeReport {
+    pub program_id: String,
+    pub instruction_index: Option<usize>,
+    pub units_consumed: u64,
+    pub limit: u64,
+}
+
+/// Prints the units consumed by the instruction as a single `Compute: {json}` line.
+pub fn report_compute_units(program_id: &Pubkey, instruction_index: Option<usize>, units_consumed: u64) {
+    let report = ComputeReport {
+        program_id: program_id.to_string(),
+        instruction_index,
+        units_consumed,
+        limit: get_compute_meter().limit,
+    };
+    println!("Compute: {}", serde_json::to_string(&report).unwrap());
+}
@@ -1,31 +1,312 @@
 
 /// Cross-Program Invocations
 ///
+use serde::{Deserialize, Serialize};
 use solana_program::{
-    instruction::{AccountMeta, Instruction},
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program::MAX_RETURN_DATA,
+    program_error::ProgramError,
     pubkey::Pubkey,
 };
+use std::{collections::HashMap, str::FromStr, sync::Mutex};
 
+use crate::{
+    account_manager::{self, AccountFileData},
+    adapter::{check_readonly_accounts, check_rent_exemption, snapshot_readonly_accounts, snapshot_rent_state, SolanaEntrypoint},
+    address_lookup_table, compute_budget, log_collector, owner_manager, spl, system_program,
+};
+
+lazy_static::lazy_static! {
+    static ref PROGRAMS: Mutex<HashMap<Pubkey, SolanaEntrypoint>> = Mutex::new(HashMap::new());
+    static ref INVOKED_PROGRAMS: Mutex<Vec<Pubkey>> = Mutex::new(vec![]);
+    static ref STACK_HEIGHT: Mutex<u64> = Mutex::new(1);
+    static ref RETURN_DATA: Mutex<(Pubkey, Vec<u8>)> = Mutex::new((Pubkey::default(), vec![]));
+    static ref RESPONSE_ACCOUNTS: Mutex<Vec<CpiAccount>> = Mutex::new(vec![]);
+}
+
+/// Makes the CPIs to the program run in this process, on the same
+/// `AccountInfo`s as its caller. The other programs are still spawned from
+/// `SOLANA_BIN_PATH`.
+pub fn register_program(program_id: Pubkey, entrypoint: SolanaEntrypoint) {
+    PROGRAMS.lock().unwrap().insert(program_id, entrypoint);
+}
+
+pub fn unregister_program(program_id: &Pubkey) {
+    PROGRAMS.lock().unwrap().remove(program_id);
+}
+
+/// The registered program, or the built-in one, which always runs in process.
+pub fn get_registered_program(program_id: &Pubkey) -> Option<SolanaEntrypoint> {
+    if let Some(entrypoint) = PROGRAMS.lock().unwrap().get(program_id) {
+        return Some(*entrypoint);
+    }
+    if system_program::check_id(program_id) {
+        return Some(system_program::process_instruction);
+    }
+    if address_lookup_table::check_id(program_id) {
+        return Some(address_lookup_table::process_instruction);
+    }
+    spl::get_program(program_id)
+}
+
+/// The registered program running now, if any. The syscalls of a program
+/// called in process go through the stubs of the first caller.
+pub fn get_invoked_program() -> Option<Pubkey> {
+    INVOKED_PROGRAMS.lock().unwrap().last().copied()
+}
+
+/// Restores the state of the caller, even if the called program panics.
+struct InvokeGuard {
+    stack_height: u64,
+    owner_pointers: usize,
+}
+
+impl Drop for InvokeGuard {
+    fn drop(&mut self) {
+        INVOKED_PROGRAMS.lock().unwrap().pop();
+        set_stack_height(self.stack_height);
+        owner_manager::truncate_ptrs(self.owner_pointers);
+    }
+}
+
+/// Runs a registered program on the `AccountInfo`s of its caller, with the
+/// privileges of the instruction. Its changes are seen by the caller right
+/// away and persisted with the caller's accounts.
+pub fn invoke_registered(
+    entrypoint: SolanaEntrypoint,
+    caller_program_id: &Pubkey,
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let caller_signers: Vec<Pubkey> = account_infos
+        .iter()
+        .filter(|account| account.is_signer)
+        .map(|account| *account.key)
+        .collect();
+    check_signature(caller_program_id, instruction, &caller_signers, signers_seeds)?;
+    let mut accounts: Vec<AccountInfo> = vec![];
+    for meta in instruction.accounts.iter() {
+        let account_info = match account_infos.iter().find(|account| account.key == &meta.pubkey) {
+            Some(account_info) => account_info,
+            None => {
+                log_collector::log(&format!("Instruction references an unknown account {}", meta.pubkey));
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+        };
+        if meta.is_writable && !account_info.is_writable {
+            log_collector::log(&format!("{}'s writable privilege escalated", meta.pubkey));
+            return Err(ProgramError::InvalidArgument);
+        }
+        let mut account_info = account_info.clone();
+        account_info.is_signer = meta.is_signer;
+        account_info.is_writable = meta.is_writable;
+        accounts.push(account_info);
+    }
+
+    let stack_height = get_stack_height();
+    let _guard = InvokeGuard {
+        stack_height,
+        owner_pointers: owner_manager::pointers_len(),
+    };
+    // the clones share the data and the lamports, the owners need their pointers
+    for account in accounts.iter_mut() {
+        let p: *mut &Pubkey = std::ptr::addr_of_mut!(account.owner);
+        owner_manager::add_ptr(p as *mut Pubkey, *account.key);
+    }
+    INVOKED_PROGRAMS.lock().unwrap().push(instruction.program_id);
+    set_stack_height(stack_height + 1);
+
+    let program_id = &instruction.program_id;
+    let meter = compute_budget::get_compute_meter();
+    log_collector::program_invoke(program_id, stack_height + 1);
+    reset_return_data(program_id);
+    let readonly_snapshot = snapshot_readonly_accounts(&accounts);
+    let rent_snapshot = snapshot_rent_state(&accounts);
+    let result = compute_budget::run_metered(|| entrypoint(program_id, &accounts, &instruction.data))
+        .and_then(|_| check_readonly_accounts(&accounts, &readonly_snapshot))
+        .and_then(|_| check_rent_exemption(&accounts, &rent_snapshot));
+    log_collector::program_return_data();
+    let consumed = compute_budget::get_compute_meter().consumed - meter.consumed;
+    log_collector::program_consumed(program_id, consumed, meter.remaining());
+    log_collector::program_result(program_id, &result);
+    result
+}
+
+/// 1 for the instructions of the transaction, 2 for the programs they call and so on.
+pub fn set_stack_height(value: u64) {
+    *STACK_HEIGHT.lock().unwrap() = value;
+}
+
+pub fn get_stack_height() -> u64 {
+    *STACK_HEIGHT.lock().unwrap()
+}
+
+/// The line with the stack height of a CPI, which callers from before the
+/// logs don't send.
+pub fn set_stack_height_line(line: &str) {
+    let stack_height = match line.trim() {
+        "" => 2,
+        height => height.parse().expect("Stack height is not an integer"),
+    };
+    set_stack_height(stack_height);
+}
+
+/// A PDA of the caller signs when one of the seeds derives it.
+fn is_pda_signer(pubkey: &Pubkey, caller_program_id: &Pubkey, signers_seeds: &[&[&[u8]]]) -> bool {
+    signers_seeds
+        .iter()
+        .any(|seeds| Pubkey::create_program_address(seeds, caller_program_id).is_ok_and(|address| &address == pubkey))
+}
+
+/// The signers of the instruction must be signers of the caller too, or PDAs
+/// of the caller derived from one of the seeds.
 pub fn check_signature(
     signer_program_id: &Pubkey,
     instruction: &Instruction,
+    caller_signers: &[Pubkey],
     pda_signature: &[&[&[u8]]],
-) {
-    let internal: Vec<&AccountMeta> = instruction
-        .accounts
-        .iter()
-        .filter(|acc| !is_external(&acc.pubkey))
-        .collect();
-    for (i, acc) in internal.iter().enumerate() {
-        if acc.is_signer {
-            let expected =
-                Pubkey::create_program_address(pda_signature[i], signer_program_id).unwrap();
-            assert_eq!(expected, acc.pubkey);
+) -> ProgramResult {
+    for meta in instruction.accounts.iter().filter(|meta| meta.is_signer) {
+        if caller_signers.contains(&meta.pubkey) || is_pda_signer(&meta.pubkey, signer_program_id, pda_signature) {
+            continue;
         }
+        log_collector::log(&format!("{}'s signer privilege escalated", meta.pubkey));
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// Sets the return data of the program. Like `sol_set_return_data` on Solana,
+/// past `MAX_RETURN_DATA` bytes the program fails, with `InvalidInstructionData`.
+pub fn set_return_data(program_id: &Pubkey, data: &[u8]) {
+    if data.len() > MAX_RETURN_DATA {
+        log_collector::log(&format!("Return data too large ({} > {})", data.len(), MAX_RETURN_DATA));
+        compute_budget::abort(ProgramError::InvalidInstructionData);
     }
+    *RETURN_DATA.lock().unwrap() = (*program_id, data.to_vec());
 }
 
-fn is_external(pubkey: &Pubkey) -> bool {
-    let bytes = pubkey.to_bytes();
-    bytes[0..4] == [0, 0, 0, 0]
+/// Cleared before each instruction and each CPI.
+pub fn reset_return_data(program_id: &Pubkey) {
+    set_return_data(program_id, &[]);
+}
+
+/// The last return data set, by the program or by the last program it called,
+/// `None` when it's empty.
+pub fn get_return_data() -> Option<(Pubkey, Vec<u8>)> {
+    let return_data = RETURN_DATA.lock().unwrap();
+    if return_data.1.is_empty() {
+        return None;
+    }
+    Some(return_data.clone())
+}
+
+/// What the called program sends back to its caller on stdout.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CpiResponse {
+    pub compute_units_consumed: u64,
+    #[serde(default)]
+    pub logs: Vec<String>,
+    /// The program id and the base64 data
+    #[serde(default)]
+    pub return_data: Option<(String, String)>,
+    /// The writable accounts once the called program succeeds, it doesn't
+    /// persist them, the transaction of its caller does.
+    #[serde(default)]
+    pub accounts: Vec<CpiAccount>,
+    /// The error of the called program as returned to the runtime, like the
+    /// `code` of `adapter::ErrorReport`, `None` when it succeeds.
+    #[serde(default)]
+    pub error: Option<u64>,
+}
+
+/// An account as the called program left it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CpiAccount {
+    pub key: String,
+    pub owner: String,
+    pub lamports: u64,
+    /// base64
+    pub data: String,
+}
+
+impl CpiAccount {
+    pub fn new(key: &Pubkey, owner: &Pubkey, lamports: u64, data: &[u8]) -> Self {
+        Self {
+            key: key.to_string(),
+            owner: owner.to_string(),
+            lamports,
+            data: base64::encode(data),
+        }
+    }
+
+    pub fn decode(&self) -> (Pubkey, AccountFileData) {
+        let key = Pubkey::from_str(&self.key).expect("invalid CPI account key");
+        let account_file_data = AccountFileData {
+            owner: Pubkey::from_str(&self.owner).expect("invalid CPI account owner"),
+            data: base64::decode(&self.data).expect("invalid CPI account data"),
+            lamports: self.lamports,
+        };
+        (key, account_file_data)
+    }
+}
+
+/// The accounts to send back with the `CPI Response`, cleared when a CPI starts.
+pub fn set_response_accounts(accounts: Vec<CpiAccount>) {
+    *RESPONSE_ACCOUNTS.lock().unwrap() = accounts;
+}
+
+pub fn get_response_accounts() -> Vec<CpiAccount> {
+    RESPONSE_ACCOUNTS.lock().unwrap().clone()
+}
+
+pub const CPI_RESPONSE_PREFIX: &str = "CPI Response: ";
+
+/// Prints the response as a single `CPI Response: {json}` line once the
+/// called program returns, even when it fails.
+pub fn report_cpi_response(result: &ProgramResult) {
+    let response = CpiResponse {
+        compute_units_consumed: crate::compute_budget::get_compute_meter().consumed,
+        logs: crate::log_collector::get_logs(),
+        return_data: get_return_data().map(|(program_id, data)| (program_id.to_string(), base64::encode(data))),
+        accounts: get_response_accounts(),
+        error: result.as_ref().err().map(|error| u64::from(error.clone())),
+    };
+    println!("{}{}", CPI_RESPONSE_PREFIX, serde_json::to_string(&response).unwrap());
+}
+
+pub fn parse_cpi_response(line: &str) -> Option<CpiResponse> {
+    serde_json::from_str(line.strip_prefix(CPI_RESPONSE_PREFIX)?).ok()
+}
+
+/// Takes the return data of the called program, as its caller sees it after the CPI.
+pub fn apply_cpi_response_return_data(response: &CpiResponse) {
+    if let Some((program_id, data)) = &response.return_data {
+        let program_id = Pubkey::from_str(program_id).expect("invalid return data program id");
+        let data = base64::decode(data).expect("invalid return data");
+        set_return_data(&program_id, &data);
+    }
+}
+
+/// Gives the accounts of the called program to its caller, which only passes
+/// writable accounts to it.
+pub fn apply_cpi_response_accounts(response: &CpiResponse, account_infos: &[AccountInfo]) -> ProgramResult {
+    for account in response.accounts.iter() {
+        let (key, account_file_data) = account.decode();
+        let account_info = account_infos
+            .iter()
+            .find(|account_info| account_info.key == &key && account_info.is_writable)
+            .ok_or(ProgramError::InvalidArgument)?;
+        if account_info.data.borrow()[..] != account_file_data.data[..] {
+            account_manager::set_data(account_info, account_file_data.data);
+        }
+        **account_info.try_borrow_mut_lamports()? = account_file_data.lamports;
+        if account_info.owner != &account_file_data.owner {
+            owner_manager::change_owner(key, account_file_data.owner);
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,109 @@
+/// Ethereum (secp256k1) signatures over a Solana message
+///
+/// A relayer can submit a transaction on behalf of Ethereum users as long as
+/// the input carries their signatures. The signed digest binds the message to
+/// the dApp address and the chain id, so a signature can't be replayed on
+/// another dApp or chain, nor twice on the same one (see `replay`).
+use serde::{Deserialize, Serialize};
+use solana_program::{keccak, program_error::ProgramError, pubkey::Pubkey, secp256k1_recover::secp256k1_recover};
+
+use crate::adapter::eth_address_to_pubkey;
+
+const EIP712_DOMAIN_TYPE: &[u8] =
+    b"EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const EIP712_DOMAIN_NAME: &[u8] = b"CartesiSolana";
+const EIP712_DOMAIN_VERSION: &[u8] = b"1";
+const SOLANA_MESSAGE_TYPE: &[u8] = b"SolanaMessage(bytes message)";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EthSignatureScheme {
+    /// EIP-191 `personal_sign` of `keccak256(chainId, dAppAddress, keccak256(message))`
+    PersonalSign,
+    /// EIP-712 typed data `SolanaMessage(bytes message)`
+    TypedData,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EthSignature {
+    pub scheme: EthSignatureScheme,
+    /// 65 bytes: r, s and v (27/28 or 0/1)
+    pub signature: Vec<u8>,
+}
+
+fn uint256(value: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn address_word(address: &[u8; 20]) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+pub fn personal_sign_digest(message: &[u8], dapp_address: &[u8; 20], chain_id: u64) -> [u8; 32] {
+    let message_hash = keccak::hash(message);
+    let payload_hash = keccak::hashv(&[&uint256(chain_id), dapp_address, message_hash.as_ref()]);
+    keccak::hashv(&[b"\x19Ethereum Signed Message:\n32", payload_hash.as_ref()]).to_bytes()
+}
+
+pub fn typed_data_digest(message: &[u8], dapp_address: &[u8; 20], chain_id: u64) -> [u8; 32] {
+    let domain_separator = keccak::hashv(&[
+        keccak::hash(EIP712_DOMAIN_TYPE).as_ref(),
+        keccak::hash(EIP712_DOMAIN_NAME).as_ref(),
+        keccak::hash(EIP712_DOMAIN_VERSION).as_ref(),
+        &uint256(chain_id),
+        &address_word(dapp_address),
+    ]);
+    let struct_hash = keccak::hashv(&[
+        keccak::hash(SOLANA_MESSAGE_TYPE).as_ref(),
+        keccak::hash(message).as_ref(),
+    ]);
+    keccak::hashv(&[b"\x19\x01", domain_separator.as_ref(), struct_hash.as_ref()]).to_bytes()
+}
+
+impl EthSignature {
+    pub fn digest(&self, message: &[u8], dapp_address: &[u8; 20], chain_id: u64) -> [u8; 32] {
+        match self.scheme {
+            EthSignatureScheme::PersonalSign => personal_sign_digest(message, dapp_address, chain_id),
+            EthSignatureScheme::TypedData => typed_data_digest(message, dapp_address, chain_id),
+        }
+    }
+
+    /// Recovers the Ethereum address that signed the message.
+    pub fn recover(&self, message: &[u8], dapp_address: &[u8; 20], chain_id: u64) -> Result<[u8; 20], ProgramError> {
+        if self.signature.len() != 65 {
+            return Err(ProgramError::InvalidArgument);
+        }
+        let recovery_id = match self.signature[64] {
+            27 | 28 => self.signature[64] - 27,
+            0 | 1 => self.signature[64],
+            _ => return Err(ProgramError::InvalidArgument),
+        };
+        let digest = self.digest(message, dapp_address, chain_id);
+        let public_key = secp256k1_recover(&digest, recovery_id, &self.signature[..64])
+            .map_err(|_| ProgramError::MissingRequiredSignature)?;
+        let public_key_hash = keccak::hash(&public_key.to_bytes());
+        let mut address = [0u8; 20];
+        address.copy_from_slice(&public_key_hash.as_ref()[12..]);
+        Ok(address)
+    }
+}
+
+/// Maps every signature to the pubkey of the Ethereum address that signed it.
+pub fn recover_signers(
+    signatures: &[EthSignature],
+    message: &[u8],
+    dapp_address: &[u8; 20],
+    chain_id: u64,
+) -> Result<Vec<Pubkey>, ProgramError> {
+    signatures
+        .iter()
+        .map(|signature| {
+            signature
+                .recover(message, dapp_address, chain_id)
+                .map(|address| eth_address_to_pubkey(&address))
+        })
+        .collect()
+}
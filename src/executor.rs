@@ -6,15 +6,25 @@ use std::{
 
 use solana_program::{
     account_info::AccountInfo,
-    instruction::{CompiledInstruction, Instruction},
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, CompiledInstruction, Instruction},
+    program_error::ProgramError,
     pubkey::Pubkey,
+    sysvar,
 };
 
 use crate::{
-    account_manager::{self, create_account_manager, AccountFileData},
-    adapter::{check_header, check_signer_by_sender, load_account_info_data, set_timestamp},
-    cartesi_stub::{AccountInfoSerialize},
-    cpi, owner_manager, transaction,
+    account_manager::{self, create_account_manager, AccountFileData, AccountWrite},
+    address_lookup_table,
+    adapter::{
+        check_header, check_readonly_accounts, check_rent_exemption, check_signer_by_sender, get_chain_id,
+        get_dapp_address, load_account_info_data, report_error, set_timestamp_line, snapshot_readonly_accounts,
+        snapshot_rent_state,
+    },
+    cartesi_stub::{invoke_spawned, AccountInfoSerialize},
+    compute_budget, cpi, log_collector,
+    eth_signature::{self, EthSignature},
+    owner_manager, replay, system_program, sysvar_accounts, transaction,
 };
 
 struct DataHolder {
@@ -29,6 +39,16 @@ pub struct Executor<'a, LR: LineReader> {
     pub program_id: Option<Pubkey>,
     pub accounts: Vec<AccountInfo<'a>>,
     pub account_keys: Vec<Pubkey>,
+
+    /// When enabled the ed25519 signatures of the transaction are verified and
+    /// the keys that signed it are marked as signers, besides the `msg_sender` one.
+    pub verify_signatures: bool,
+
+    /// The program implemented by the closure of `get_processor_args`, from
+    /// `SOLANA_PROGRAM_ID` by default. The instructions for other programs run
+    /// the built-in and registered ones, or spawn them from `SOLANA_BIN_PATH`.
+    /// Without it the closure runs every program that isn't built in or registered.
+    pub host_program_id: Option<Pubkey>,
 }
 
 impl<'a, LR> Executor<'a, LR>
@@ -43,18 +63,29 @@ where
             program_id,
             accounts: vec![],
             account_keys: vec![],
+            verify_signatures: false,
+            host_program_id: std::env::var("SOLANA_PROGRAM_ID")
+                .ok()
+                .map(|value| value.parse().expect("SOLANA_PROGRAM_ID is not a valid pubkey")),
         }
     }
-    pub fn get_processor_args<F>(&'a mut self, closure_fn: F)
+    /// Runs the input through the program, the accounts are only persisted
+    /// when it returns `Ok`. Errors are also reported with `report_error`.
+    pub fn get_processor_args<F>(&'a mut self, closure_fn: F) -> ProgramResult
     where
-        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>),
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
     {
         let header = self.read_line();
         println!("header: {}", header);
         match check_header(&header) {
             crate::adapter::SmartContractType::ExternalPI => self.handle_external_call(closure_fn),
-            crate::adapter::SmartContractType::CPI => self.handle_cpi_call(closure_fn),
-        };
+            crate::adapter::SmartContractType::RelayedPI => self.handle_relayed_call(closure_fn),
+            crate::adapter::SmartContractType::CPI => {
+                let result = self.handle_cpi_call(closure_fn);
+                cpi::report_cpi_response(&result);
+                result
+            }
+        }
     }
 
     fn read_line(&mut self) -> String {
@@ -63,31 +94,48 @@ where
         current_line[..current_line.len() - 1].to_string()
     }
 
-    fn read_instruction_index(&mut self) -> usize {
+    /// A trailing line that older callers don't send, empty at the end of the input.
+    fn read_optional_line(&mut self) -> String {
+        let mut current_line = String::new();
+        match self.stdin.read_line(&mut current_line) {
+            Ok(0) | Err(_) => String::new(),
+            Ok(_) => current_line.trim_end_matches('\n').to_string(),
+        }
+    }
+
+    /// Either a single instruction index or `all`, to run every instruction
+    /// of the transaction atomically.
+    fn read_instruction_indexes(&mut self, tx: &transaction::VersionedTransaction) -> Vec<usize> {
         let instruction_index = self.read_line();
+        if instruction_index.trim() == "all" {
+            return (0..tx.message.instructions().len()).collect();
+        }
         let instruction_index: usize = instruction_index
             .trim()
             .parse()
             .expect("Input is not an integer");
-        instruction_index
+        vec![instruction_index]
     }
 
-    fn read_and_set_timestamp(&mut self) {
+    fn read_and_set_timestamp(&mut self) -> ProgramResult {
         let timestamp = self.read_line();
-        let timestamp: i64 = timestamp
-            .trim()
-            .parse()
-            .expect("Timestamp is not an integer");
-        set_timestamp(timestamp);
+        set_timestamp_line(&timestamp).inspect_err(|error| report_error(None, None, error))
     }
 
-    fn read_transaction(&mut self) -> transaction::Transaction {
+    fn read_transaction(&mut self) -> transaction::VersionedTransaction {
         let payload = self.read_line();
         let decoded = base64::decode(payload).unwrap();
-        let tx: transaction::Transaction = bincode::deserialize(&decoded).unwrap();
+        let tx: transaction::VersionedTransaction = bincode::deserialize(&decoded).unwrap();
         tx
     }
 
+    fn read_eth_signatures(&mut self) -> Vec<EthSignature> {
+        let signatures = self.read_line();
+        let signatures = base64::decode(signatures).unwrap();
+        let signatures: Vec<EthSignature> = bincode::deserialize(&signatures).unwrap();
+        signatures
+    }
+
     fn sender_bytes(&mut self, msg_sender: &String) -> Vec<u8> {
         let sender_bytes: Vec<u8> = hex::decode(&msg_sender[2..])
             .unwrap()
@@ -118,21 +166,6 @@ where
         data_holder
     }
 
-    fn get_ordered_account_keys(
-        &mut self,
-        tx: &transaction::Transaction,
-        tx_instruction: &CompiledInstruction,
-    ) -> Vec<Pubkey> {
-        let mut ordered_accounts: Vec<Pubkey> = Vec::new();
-        let tot = tx_instruction.accounts.len();
-        for j in 0..tot {
-            let index = tx_instruction.accounts[j];
-            let i: usize = index.into();
-            ordered_accounts.push(tx.message.account_keys[i].to_owned());
-        }
-        ordered_accounts
-    }
-
     fn read_cpi_instruction(&mut self) -> Instruction {
         let instruction = self.read_line();
         let instruction = base64::decode(&instruction.trim()).unwrap();
@@ -166,16 +199,22 @@ where
         solana_program::program_stubs::set_syscall_stubs(Box::new(crate::cartesi_stub::CartesiStubs { program_id: _program_id }));
     }
 
-    fn handle_cpi_call<F>(&mut self, closure_fn: F)
+    fn handle_cpi_call<F>(&mut self, closure_fn: F) -> ProgramResult
     where
-        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>),
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
     {
         let instruction = self.read_cpi_instruction();
         let accounts = self.read_cpi_accounts();
         let signers_seeds = self.read_signers_seeds();
 
-        self.read_and_set_timestamp();
+        self.read_and_set_timestamp()?;
         let caller_program_id = self.read_pubkey();
+        let compute_units = self.read_optional_line();
+        compute_budget::set_compute_budget_line(&compute_units);
+        let stack_height = self.read_optional_line();
+        cpi::set_stack_height_line(&stack_height);
+        log_collector::reset_logs();
+        cpi::set_response_accounts(vec![]);
 
         let pda_signature: Vec<Vec<&[u8]>> = signers_seeds
             .iter()
@@ -185,7 +224,15 @@ where
         let pda_signature: Vec<&[&[u8]]> = pda_signature.iter().map(|x| x.as_slice()).collect();
         let pda_signature: &[&[&[u8]]] = pda_signature.as_slice();
 
-        cpi::check_signature(&caller_program_id, &instruction, &pda_signature);
+        let caller_signers: Vec<Pubkey> = accounts
+            .iter()
+            .filter(|account| account.is_signer)
+            .map(|account| account.key)
+            .collect();
+        if let Err(error) = cpi::check_signature(&caller_program_id, &instruction, &caller_signers, pda_signature) {
+            report_error(Some(&instruction.program_id), None, &error);
+            return Err(error);
+        }
         let pubkeys: Vec<Pubkey> = instruction.accounts.iter().map(|acc| acc.pubkey).collect();
 
         let mut ordered_accounts = vec![];
@@ -196,13 +243,26 @@ where
                 None => panic!("Account not found {:?}", key),
             }
         }
+        let escalated = instruction
+            .accounts
+            .iter()
+            .zip(ordered_accounts.iter())
+            .find(|(meta, account)| meta.is_writable && !account.is_writable);
+        if let Some((meta, _)) = escalated {
+            log_collector::log(&format!("{}'s writable privilege escalated", meta.pubkey));
+            let error = ProgramError::InvalidArgument;
+            report_error(Some(&instruction.program_id), None, &error);
+            return Err(error);
+        }
+        let writable: Vec<bool> = instruction.accounts.iter().map(|meta| meta.is_writable).collect();
 
         let mut accounts: Vec<AccountInfo> = ordered_accounts
             .iter_mut()
-            .map(|account| AccountInfo {
+            .zip(instruction.accounts.iter())
+            .map(|(account, meta)| AccountInfo {
                 key: &account.key,
-                is_signer: account.is_signer,
-                is_writable: true,
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
                 lamports: Rc::new(RefCell::new(&mut account.lamports)),
                 data: Rc::new(RefCell::new(&mut account.data)),
                 owner: &account.owner,
@@ -220,14 +280,38 @@ where
         }
         self.setup_cartesi_stubs(instruction.program_id.clone());
 
-        closure_fn(&instruction.program_id, &accounts, &instruction.data);
+        let readonly_snapshot = snapshot_readonly_accounts(&accounts);
+        let rent_snapshot = snapshot_rent_state(&accounts);
+        log_collector::program_invoke(&instruction.program_id, cpi::get_stack_height());
+        cpi::reset_return_data(&instruction.program_id);
+        let result = compute_budget::run_metered(|| closure_fn(&instruction.program_id, &accounts, &instruction.data))
+            .and_then(|_| check_readonly_accounts(&accounts, &readonly_snapshot))
+            .and_then(|_| check_rent_exemption(&accounts, &rent_snapshot));
+        log_collector::program_return_data();
+        let meter = compute_budget::get_compute_meter();
+        log_collector::program_consumed(&instruction.program_id, meter.consumed, meter.limit);
+        log_collector::program_result(&instruction.program_id, &result);
+        if let Err(error) = result {
+            report_error(Some(&instruction.program_id), None, &error);
+            return Err(error);
+        }
         let new_owners: Vec<Pubkey> = accounts
             .iter()
             .map(|account| account.owner.to_owned())
             .collect();
-        let data_holder: Vec<DataHolder> = self.to_data_holder(ordered_accounts);
-
-        persist_accounts(data_holder, new_owners);
+        let mut data_holder: Vec<DataHolder> = self.to_data_holder(ordered_accounts);
+        for (i, holder) in data_holder.iter_mut().enumerate() {
+            holder.owner = new_owners[i];
+        }
+        // persisted by the transaction of the caller, see `CpiResponse`
+        let response_accounts = data_holder
+            .iter()
+            .zip(writable.iter())
+            .filter(|(holder, is_writable)| **is_writable && !sysvar_accounts::is_sysvar(&holder.pubkey))
+            .map(|(holder, _)| cpi::CpiAccount::new(&holder.pubkey, &holder.owner, holder.lamports, &holder.data))
+            .collect();
+        cpi::set_response_accounts(response_accounts);
+        Ok(())
     }
 
     fn to_data_holder(&mut self, ordered_accounts: Vec<AccountInfoSerialize>) -> Vec<DataHolder> {
@@ -236,7 +320,7 @@ where
             .map(|account| DataHolder {
                 pubkey: account.key.to_owned(),
                 lamports: account.lamports,
-                data: account.data.to_vec(),
+                data: account_manager::get_resized(&account.key).unwrap_or_else(|| account.data.to_vec()),
                 owner: account.owner.to_owned(),
             })
             .collect();
@@ -244,93 +328,346 @@ where
     }
 
     fn is_executable(&mut self, program_id: &Pubkey) -> bool {
-        let path = std::path::Path::new(&crate::adapter::get_binary_base_path()).join(program_id.to_string());
-        path.exists()
+        cpi::get_registered_program(program_id).is_some() || is_deployed(program_id)
     }
 
-    fn handle_external_call<F>(&'a mut self, closure_fn: F)
+    /// Whether the closure of `get_processor_args` implements the program.
+    fn runs_closure(&self, program_id: &Pubkey) -> bool {
+        self.host_program_id.is_none_or(|host_program_id| host_program_id == *program_id)
+    }
+
+    /// The programs that are neither built in, registered nor the host are
+    /// spawned from `SOLANA_BIN_PATH`.
+    fn is_spawned(&self, program_id: &Pubkey) -> bool {
+        !compute_budget::check_id(program_id)
+            && cpi::get_registered_program(program_id).is_none()
+            && !self.runs_closure(program_id)
+            && is_deployed(program_id)
+    }
+
+    fn handle_external_call<F>(&'a mut self, closure_fn: F) -> ProgramResult
     where
-        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>),
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
     {
         let msg_sender = self.read_line(); // the order of read commands is important!
         let sender_bytes = self.sender_bytes(&msg_sender);
         let tx = self.read_transaction();
-        let instruction_index = self.read_instruction_index();
-        self.read_and_set_timestamp();
-        let tx_instruction = &tx.message.instructions[instruction_index];
-        let pidx: usize = (tx_instruction.program_id_index).into();
-        let program_id: &Pubkey = &tx.message.account_keys[pidx];
-        self.setup_cartesi_stubs(program_id.clone());
-
-        self.program_id = Some(program_id.to_owned());
-        let program_id = self.program_id.unwrap();
-        let ordered_accounts = self.get_ordered_account_keys(&tx, tx_instruction);
-
-        let mut data_holder = self.load_persisted_data(&ordered_accounts);
-        let mut accounts = vec![];
-        for holder in data_holder.iter_mut() {
-            let key = &holder.pubkey;
-            let is_signer = check_signer_by_sender(key, &sender_bytes);
-            let account_info = AccountInfo {
-                key: &holder.pubkey,
-                is_signer,
-                is_writable: true,
-                lamports: Rc::new(RefCell::new(&mut holder.lamports)),
-                data: Rc::new(RefCell::new(&mut holder.data)),
-                owner: &holder.owner,
-                executable: self.is_executable(key),
-                rent_epoch: 1,
-            };
-            accounts.push(account_info);
+        let instruction_indexes = self.read_instruction_indexes(&tx);
+        self.read_and_set_timestamp()?;
+        self.execute_transaction(closure_fn, &sender_bytes, &tx, &instruction_indexes, vec![])
+    }
+
+    /// Like an external call, but the input also carries Ethereum signatures
+    /// over the message, so a relayer can submit it on behalf of the signers.
+    fn handle_relayed_call<F>(&'a mut self, closure_fn: F) -> ProgramResult
+    where
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
+    {
+        let msg_sender = self.read_line(); // the order of read commands is important!
+        let sender_bytes = self.sender_bytes(&msg_sender);
+        let tx = self.read_transaction();
+        let eth_signatures = self.read_eth_signatures();
+        let instruction_indexes = self.read_instruction_indexes(&tx);
+        self.read_and_set_timestamp()?;
+        let eth_signers = eth_signature::recover_signers(
+            &eth_signatures,
+            &tx.message.serialize(),
+            &get_dapp_address(),
+            get_chain_id(),
+        );
+        match eth_signers {
+            Ok(eth_signers) => {
+                self.execute_transaction(closure_fn, &sender_bytes, &tx, &instruction_indexes, eth_signers)
+            }
+            Err(error) => {
+                report_error(None, None, &error);
+                Err(error)
+            }
         }
+    }
 
-        // the addresses changes when you push to vec
-        // so we need to get the pointers here, after
-        let tot = accounts.len();
-        for j in 0..tot {
-            let p: *mut &Pubkey = std::ptr::addr_of_mut!(accounts[j].owner);
-            owner_manager::add_ptr(p as *mut Pubkey, accounts[j].key.clone());
+    /// Runs the transaction and prints its logs, see `log_collector`.
+    fn execute_transaction<F>(
+        &'a mut self,
+        closure_fn: F,
+        sender_bytes: &[u8],
+        tx: &transaction::VersionedTransaction,
+        instruction_indexes: &[usize],
+        extra_signers: Vec<Pubkey>,
+    ) -> ProgramResult
+    where
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
+    {
+        log_collector::reset_logs();
+        cpi::set_stack_height(1);
+        let result = self.run_transaction(closure_fn, sender_bytes, tx, instruction_indexes, extra_signers);
+        log_collector::report_logs();
+        result
+    }
+
+    /// Runs the instructions in order over one in-memory copy of the accounts,
+    /// which is only persisted once all of them succeed. Programs called
+    /// through CPI send their accounts back instead of persisting them.
+    fn run_transaction<F>(
+        &'a mut self,
+        closure_fn: F,
+        sender_bytes: &[u8],
+        tx: &transaction::VersionedTransaction,
+        instruction_indexes: &[usize],
+        extra_signers: Vec<Pubkey>,
+    ) -> ProgramResult
+    where
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
+    {
+        let mut signers = extra_signers;
+        let message = tx.message.serialize();
+        let loaded = address_lookup_table::load_account_keys(&tx.message).and_then(|loaded| {
+            if self.verify_signatures {
+                signers.append(&mut tx.verify_signatures()?);
+            }
+            if !signers.is_empty() && replay::is_consumed(&*create_account_manager(), &message) {
+                log_collector::log("Transaction already processed");
+                return Err(ProgramError::InvalidArgument);
+            }
+            Ok(loaded)
+        });
+        let (account_keys, loaded_addresses) = match loaded {
+            Ok(loaded) => loaded,
+            Err(error) => {
+                report_error(None, None, &error);
+                return Err(error);
+            }
+        };
+
+        let compute_budget = compute_budget::compute_budget_from_instructions(
+            tx.message
+                .instructions()
+                .iter()
+                .map(|instruction| (&account_keys[instruction.program_id_index as usize], &instruction.data[..])),
+        );
+        match compute_budget {
+            Ok((unit_limit, unit_price)) => compute_budget::set_compute_budget(unit_limit, unit_price),
+            Err(error) => {
+                report_error(None, None, &error);
+                return Err(error);
+            }
         }
 
-        closure_fn(&program_id, &accounts, &tx_instruction.data);
-        let new_owners: Vec<Pubkey> = accounts
+        let writable: Vec<bool> = (0..account_keys.len())
+            .map(|i| tx.is_writable(i, &loaded_addresses))
+            .collect();
+        let is_signer: Vec<bool> = account_keys
             .iter()
-            .map(|account| account.owner.to_owned())
+            .map(|key| signers.contains(key) || check_signer_by_sender(key, sender_bytes))
             .collect();
-        persist_accounts(data_holder, new_owners);
+
+        let mut cache = self.load_persisted_data(&account_keys);
+        let instructions_sysvar = account_keys.iter().position(sysvar::instructions::check_id);
+        if let Some(i) = instructions_sysvar {
+            let instructions = tx.message.instructions();
+            let (data, lamports, owner) =
+                sysvar_accounts::load_instructions_account(&account_keys, instructions, &writable, &is_signer);
+            cache[i].data = data;
+            cache[i].lamports = lamports;
+            cache[i].owner = owner;
+        }
+        let mut touched = vec![false; account_keys.len()];
+        for instruction_index in instruction_indexes.iter() {
+            if let Some(i) = instructions_sysvar {
+                sysvar_accounts::set_current_instruction(&mut cache[i].data, *instruction_index);
+            }
+            let tx_instruction = &tx.message.instructions()[*instruction_index];
+            let pidx: usize = (tx_instruction.program_id_index).into();
+            let program_id = tx.message.static_account_keys()[pidx];
+            let consumed = compute_budget::get_compute_meter().consumed;
+            // a spawned program logs its own invoke, consumption and result
+            let spawned = self.is_spawned(&program_id);
+            if !spawned {
+                log_collector::program_invoke(&program_id, 1);
+            }
+            cpi::reset_return_data(&program_id);
+            let result = self.execute_instruction(
+                &closure_fn,
+                program_id,
+                tx_instruction,
+                &writable,
+                &is_signer,
+                &mut cache,
+            );
+            let meter = compute_budget::get_compute_meter();
+            let units_consumed = meter.consumed - consumed;
+            if !spawned {
+                log_collector::program_return_data();
+                log_collector::program_consumed(&program_id, units_consumed, meter.limit - consumed);
+                log_collector::program_result(&program_id, &result);
+            }
+            compute_budget::report_compute_units(&program_id, Some(*instruction_index), units_consumed);
+            if let Err(error) = result {
+                report_error(Some(&program_id), Some(*instruction_index), &error);
+                return Err(error);
+            }
+            for index in tx_instruction.accounts.iter() {
+                touched[*index as usize] = true;
+            }
+        }
+
+        let data_holder: Vec<DataHolder> = cache
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| touched[*i] && writable[*i])
+            .map(|(_, holder)| holder)
+            .collect();
+        // the signatures can't be replayed once the transaction is persisted
+        let consumed = if signers.is_empty() { vec![] } else { vec![replay::consume(&message)] };
+        persist_accounts(&data_holder, consumed);
+        Ok(())
+    }
+
+    fn execute_instruction<F>(
+        &mut self,
+        closure_fn: &F,
+        program_id: Pubkey,
+        tx_instruction: &CompiledInstruction,
+        writable: &[bool],
+        is_signer: &[bool],
+        cache: &mut [DataHolder],
+    ) -> ProgramResult
+    where
+        F: for<'b> Fn(&'b Pubkey, &'b Vec<AccountInfo<'b>>, &'b Vec<u8>) -> ProgramResult,
+    {
+        self.setup_cartesi_stubs(program_id);
+        self.program_id = Some(program_id);
+
+        let result: std::result::Result<Vec<(usize, Pubkey)>, ProgramError> = {
+            // an account passed more than once shares the same AccountInfo data
+            let mut account_infos: Vec<Option<AccountInfo>> = vec![];
+            for (i, holder) in cache.iter_mut().enumerate() {
+                if !tx_instruction.accounts.contains(&(i as u8)) {
+                    account_infos.push(None);
+                    continue;
+                }
+                let executable = self.is_executable(&holder.pubkey);
+                account_infos.push(Some(AccountInfo {
+                    key: &holder.pubkey,
+                    is_signer: is_signer[i],
+                    is_writable: writable[i],
+                    lamports: Rc::new(RefCell::new(&mut holder.lamports)),
+                    data: Rc::new(RefCell::new(&mut holder.data)),
+                    owner: &holder.owner,
+                    executable,
+                    rent_epoch: 1,
+                }));
+            }
+            let mut accounts: Vec<AccountInfo> = tx_instruction
+                .accounts
+                .iter()
+                .map(|index| account_infos[*index as usize].clone().unwrap())
+                .collect();
+
+            // the addresses changes when you push to vec
+            // so we need to get the pointers here, after
+            let tot = accounts.len();
+            for j in 0..tot {
+                let p: *mut &Pubkey = std::ptr::addr_of_mut!(accounts[j].owner);
+                owner_manager::add_ptr(p as *mut Pubkey, accounts[j].key.clone());
+            }
+
+            let readonly_snapshot = snapshot_readonly_accounts(&accounts);
+            let rent_snapshot = snapshot_rent_state(&accounts);
+            let result = compute_budget::run_metered(|| {
+                if compute_budget::check_id(&program_id) {
+                    // already applied to the whole transaction
+                    compute_budget::consume(compute_budget::COMPUTE_BUDGET_PROGRAM_UNITS)
+                } else if address_lookup_table::check_id(&program_id) {
+                    compute_budget::consume(compute_budget::ADDRESS_LOOKUP_TABLE_PROGRAM_UNITS)?;
+                    address_lookup_table::process_instruction(&program_id, &accounts, &tx_instruction.data)
+                } else if system_program::check_id(&program_id) {
+                    compute_budget::consume(compute_budget::SYSTEM_PROGRAM_UNITS)?;
+                    system_program::process_instruction(&program_id, &accounts, &tx_instruction.data)
+                } else if let Some(entrypoint) = cpi::get_registered_program(&program_id) {
+                    entrypoint(&program_id, &accounts, &tx_instruction.data)
+                } else if self.runs_closure(&program_id) {
+                    closure_fn(&program_id, &accounts, &tx_instruction.data)
+                } else if is_deployed(&program_id) {
+                    let instruction = Instruction {
+                        program_id,
+                        accounts: accounts
+                            .iter()
+                            .map(|account| AccountMeta {
+                                pubkey: *account.key,
+                                is_signer: account.is_signer,
+                                is_writable: account.is_writable,
+                            })
+                            .collect(),
+                        data: tx_instruction.data.clone(),
+                    };
+                    invoke_spawned(&program_id, &instruction, &accounts, &[], 1)
+                } else {
+                    log_collector::log(&format!("Program {} is not deployed", program_id));
+                    Err(ProgramError::IncorrectProgramId)
+                }
+            });
+            result
+                .and_then(|_| check_readonly_accounts(&accounts, &readonly_snapshot))
+                .and_then(|_| check_rent_exemption(&accounts, &rent_snapshot))
+                .map(|_| {
+                    tx_instruction
+                        .accounts
+                        .iter()
+                        .zip(accounts.iter())
+                        .map(|(index, account)| (*index as usize, account.owner.to_owned()))
+                        .collect()
+                })
+        };
+        if let Ok(new_owners) = &result {
+            for (i, owner) in new_owners.iter() {
+                let holder = &mut cache[*i];
+                holder.owner = *owner;
+                if let Some(data) = account_manager::get_resized(&holder.pubkey) {
+                    holder.data = data;
+                }
+            }
+        }
+        // the AccountInfos are gone, so are the owner pointers and resized data
+        owner_manager::clear();
+        account_manager::clear();
+        result.map(|_| ())
     }
 }
 
+/// Whether the program has a binary in `SOLANA_BIN_PATH`.
+fn is_deployed(program_id: &Pubkey) -> bool {
+    std::path::Path::new(&crate::adapter::get_binary_base_path())
+        .join(program_id.to_string())
+        .exists()
+}
+
 pub fn create_executor<'b>() -> Executor<'b, DefaultStdin> {
     let stdin = DefaultStdin {};
     Executor::create_with_stdin(stdin)
 }
 
-fn persist_accounts(data_holder: Vec<DataHolder>, new_owners: Vec<Pubkey>) {
-    let account_manager = create_account_manager();
-    for (i, holder) in data_holder.iter().enumerate() {
+/// Commits the accounts, after `writes`, in one go.
+fn persist_accounts(data_holder: &[DataHolder], mut writes: Vec<AccountWrite>) {
+    for holder in data_holder.iter().filter(|holder| !sysvar_accounts::is_sysvar(&holder.pubkey)) {
         let key = &holder.pubkey;
-        let res = account_manager::get_resized(key);
-        let mut final_data = holder.data.to_owned();
-        if let Some(data) = res {
-            final_data = data;
-        }
         let account_file_data = AccountFileData {
-            owner: new_owners[i].to_owned(),
-            data: final_data.to_owned(),
+            owner: holder.owner.to_owned(),
+            data: holder.data.to_owned(),
             lamports: holder.lamports,
         };
         if account_file_data.lamports <= 0 {
-            account_manager.delete_account(&key).unwrap();
             println!("!e deleted = {:?}", key);
+            writes.push((*key, None));
         } else {
-            account_manager
-                .write_account(&key, &account_file_data)
-                .unwrap();
             println!("  e) saved = {:?};", key);
             println!("     owner = {:?}", account_file_data.owner.to_string());
+            writes.push((*key, Some(account_file_data)));
         }
     }
+    create_account_manager()
+        .commit_accounts(&writes)
+        .expect("failed to persist the accounts");
 }
 
 pub trait LineReader {
@@ -5,3 +5,12 @@ pub mod account_manager;
 pub mod cartesi_stub;
 pub mod cpi;
 pub mod executor;
+pub mod address_lookup_table;
+pub mod system_program;
+pub mod spl;
+pub mod eth_signature;
+pub mod replay;
+pub mod state_tree;
+pub mod sysvar_accounts;
+pub mod compute_budget;
+pub mod log_collector;
@@ -0,0 +1,122 @@
+/// Program logs
+///
+/// What the programs log through `msg!`, `sol_log_64`, `sol_log_data` and
+/// `Pubkey::log` (`sol_log_64` and `Pubkey::log` go through `sol_log` when
+/// compiled natively, `sol_log_data` has a stub of its own) is kept per
+/// transaction with the framing of Solana:
+///
+/// ```text
+/// Program <id> invoke [1]
+/// Program log: <message>
+/// Program data: <base64> <base64>
+/// Program <id> invoke [2]
+/// ...
+/// Program return: <id> <base64>
+/// Program <id> consumed <units> of <limit> compute units
+/// Program <id> success
+/// ```
+///
+/// Past `LOG_MESSAGES_BYTES_LIMIT` bytes the logs are truncated, like on a
+/// Solana validator. The logs of a CPI come back to its caller in the
+/// `CPI Response` line.
+use serde::{Deserialize, Serialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::sync::Mutex;
+
+pub const LOG_MESSAGES_BYTES_LIMIT: usize = 10 * 1000;
+
+struct LogCollector {
+    messages: Vec<String>,
+    bytes_written: usize,
+    limit_warning: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref LOG_COLLECTOR: Mutex<LogCollector> = Mutex::new(LogCollector {
+        messages: vec![],
+        bytes_written: 0,
+        limit_warning: false,
+    });
+}
+
+pub fn reset_logs() {
+    let mut collector = LOG_COLLECTOR.lock().unwrap();
+    collector.messages.clear();
+    collector.bytes_written = 0;
+    collector.limit_warning = false;
+}
+
+pub fn get_logs() -> Vec<String> {
+    LOG_COLLECTOR.lock().unwrap().messages.clone()
+}
+
+pub fn log(message: &str) {
+    println!("{}", message);
+    collect(message);
+}
+
+/// Adds the logs of a CPI, the called program has printed them already.
+pub fn append_logs(messages: &[String]) {
+    for message in messages.iter() {
+        collect(message);
+    }
+}
+
+fn collect(message: &str) {
+    let mut collector = LOG_COLLECTOR.lock().unwrap();
+    if collector.limit_warning {
+        return;
+    }
+    let bytes_written = collector.bytes_written.saturating_add(message.len());
+    if bytes_written > LOG_MESSAGES_BYTES_LIMIT {
+        collector.messages.push("Log truncated".to_string());
+        collector.limit_warning = true;
+        return;
+    }
+    collector.bytes_written = bytes_written;
+    collector.messages.push(message.to_string());
+}
+
+pub fn program_invoke(program_id: &Pubkey, stack_height: u64) {
+    log(&format!("Program {} invoke [{}]", program_id, stack_height));
+}
+
+pub fn program_log(message: &str) {
+    log(&format!("Program log: {}", message));
+}
+
+pub fn program_data(fields: &[&[u8]]) {
+    let fields: Vec<String> = fields.iter().map(base64::encode).collect();
+    log(&format!("Program data: {}", fields.join(" ")));
+}
+
+/// Logged after the instruction when it has set return data.
+pub fn program_return_data() {
+    if let Some((program_id, data)) = crate::cpi::get_return_data() {
+        log(&format!("Program return: {} {}", program_id, base64::encode(data)));
+    }
+}
+
+pub fn program_consumed(program_id: &Pubkey, consumed: u64, limit: u64) {
+    log(&format!("Program {} consumed {} of {} compute units", program_id, consumed, limit));
+}
+
+/// The end of the instruction, `success` or `failed: <error>`.
+pub fn program_result(program_id: &Pubkey, result: &Result<(), ProgramError>) {
+    match result {
+        Ok(()) => log(&format!("Program {} success", program_id)),
+        Err(error) => log(&format!("Program {} failed: {}", program_id, error)),
+    }
+}
+
+/// What is printed once the transaction ends, to be emitted as a report.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LogsReport {
+    pub logs: Vec<String>,
+}
+
+/// Prints the logs of the transaction as a single `Logs: {json}` line.
+pub fn report_logs() {
+    let report = LogsReport { logs: get_logs() };
+    println!("Logs: {}", serde_json::to_string(&report).unwrap());
+}
@@ -1,7 +1,9 @@
-use solana_program::{pubkey::Pubkey, msg};
+use solana_program::pubkey::Pubkey;
 
 
-static mut OWNERS: Vec<Pubkey> = Vec::new();
+// boxed so the owners keep their address when the vec grows
+#[allow(clippy::vec_box)]
+static mut OWNERS: Vec<Box<Pubkey>> = Vec::new();
 static mut POINTERS: Vec<(*mut &Pubkey, Pubkey)> = Vec::new();
 
 pub fn clear() {
@@ -17,25 +19,42 @@ pub fn add_ptr(p: *mut Pubkey, key: Pubkey) {
     }
 }
 
+/// How many `AccountInfo`s are registered, to drop the ones added after with
+/// `truncate_ptrs` once they are gone.
+pub fn pointers_len() -> usize {
+    unsafe { (*std::ptr::addr_of!(POINTERS)).len() }
+}
+
+pub fn truncate_ptrs(len: usize) {
+    unsafe {
+        (*std::ptr::addr_of_mut!(POINTERS)).truncate(len);
+    }
+}
+
+/// Changes the owner of every `AccountInfo` registered for the key, an
+/// account may be passed more than once to the same instruction.
 pub fn change_owner<'a>(key: Pubkey, new_owner: Pubkey) {
     unsafe {
-        let tot = OWNERS.len();
-        OWNERS.push(new_owner);
+        OWNERS.push(Box::new(new_owner));
+        let owner: &Pubkey = OWNERS.last().unwrap();
+        let mut found = false;
         let pointers = &POINTERS;
         for (i, item) in pointers.iter().enumerate() {
-            if item.1.to_string() == key.to_string() {
+            if item.1 == key {
                 let old = *item.0;
-                *item.0 = &OWNERS[tot];
-                msg!(
+                *item.0 = owner;
+                println!(
                     "change_owner: i[{}] account[{:?}] old[{:?}] new[{:?}]",
                     i,
                     key,
                     old,
                     new_owner
                 );
-                return;
+                found = true;
             }
         }
-        panic!("Account [{:?}] not found, change owner failed.", key);
+        if !found {
+            panic!("Account [{:?}] not found, change owner failed.", key);
+        }
     }
 }
@@ -0,0 +1,42 @@
+/// Replay protection of signed transactions
+///
+/// The Ethereum signatures of a relayed input, and the ed25519 ones checked
+/// with `Executor::verify_signatures`, don't depend on who sends the input, so
+/// anyone could send it again. The hash of the message of every signed
+/// transaction is stored as an account, persisted along with the accounts of
+/// the transaction, and a message that is already there is rejected. To send
+/// the same instructions again the signers change the recent blockhash of the
+/// message, which works as a nonce.
+use solana_program::{keccak, pubkey::Pubkey};
+
+use crate::account_manager::{AccountFileData, AccountStore, AccountWrite};
+
+const CONSUMED_MESSAGE_SEED: &[u8] = b"cartesi-solana:consumed-message";
+
+/// The owner of the consumed message accounts, a hash that no program has.
+pub fn id() -> Pubkey {
+    Pubkey::new_from_array(keccak::hash(CONSUMED_MESSAGE_SEED).to_bytes())
+}
+
+/// The account that marks the message as consumed.
+pub fn consumed_message_key(message: &[u8]) -> Pubkey {
+    let message_hash = keccak::hash(message);
+    Pubkey::new_from_array(keccak::hashv(&[CONSUMED_MESSAGE_SEED, message_hash.as_ref()]).to_bytes())
+}
+
+/// Whether a transaction with the message was already persisted.
+pub fn is_consumed(account_store: &dyn AccountStore, message: &[u8]) -> bool {
+    account_store
+        .read_account(&consumed_message_key(message))
+        .is_ok_and(|account| account.owner == id())
+}
+
+/// The write that marks the message as consumed, see `AccountStore::commit_accounts`.
+pub fn consume(message: &[u8]) -> AccountWrite {
+    let account_file_data = AccountFileData {
+        owner: id(),
+        data: vec![],
+        lamports: 1,
+    };
+    (consumed_message_key(message), Some(account_file_data))
+}
@@ -0,0 +1,29 @@
+/// Built-in SPL Token and Associated Token Account programs
+///
+/// With the `spl` feature their processors run in process under their
+/// canonical program ids, for the instructions of a transaction and for CPIs,
+/// so there's no need to ship their binaries in `SOLANA_BIN_PATH`.
+use solana_program::pubkey::Pubkey;
+
+use crate::adapter::SolanaEntrypoint;
+
+#[cfg(feature = "spl")]
+pub use spl_associated_token_account;
+#[cfg(feature = "spl")]
+pub use spl_token;
+
+#[cfg(feature = "spl")]
+pub fn get_program(program_id: &Pubkey) -> Option<SolanaEntrypoint> {
+    if spl_token::check_id(program_id) {
+        return Some(spl_token::processor::Processor::process);
+    }
+    if spl_associated_token_account::check_id(program_id) {
+        return Some(spl_associated_token_account::processor::process_instruction);
+    }
+    None
+}
+
+#[cfg(not(feature = "spl"))]
+pub fn get_program(_program_id: &Pubkey) -> Option<SolanaEntrypoint> {
+    None
+}
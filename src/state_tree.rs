@@ -0,0 +1,257 @@
+/// Sparse Merkle tree over the accounts
+///
+/// A 256 levels keccak tree where the path of an account is its pubkey, the
+/// most significant bit first, and the leaf is
+/// `keccak(pubkey || owner || lamports (u64 big endian) || data)`.
+/// Empty subtrees hash to zero: a parent is `keccak(left || right)` unless
+/// both children are zero.
+///
+/// Only the subtrees with accounts are stored: a `Leaf` is kept at the top of
+/// a subtree with a single account, and the hashes from it up to its height
+/// are computed when needed. So updates are `O(256)` hashes and the amount of
+/// nodes stored is proportional to the amount of accounts.
+use serde::{Deserialize, Serialize};
+use solana_program::{keccak, pubkey::Pubkey};
+
+use crate::account_manager::AccountFileData;
+
+pub type Hash32 = [u8; 32];
+
+pub const ZERO_HASH: Hash32 = [0; 32];
+pub const TREE_HEIGHT: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    /// The only account of the subtree
+    Leaf { key: Pubkey, hash: Hash32 },
+    Branch { hash: Hash32 },
+}
+
+/// Where the nodes are kept, by height (0 is a leaf) and the path prefix,
+/// which is the key with the bits below the height set to zero.
+pub trait NodeStore {
+    fn get_node(&self, height: usize, prefix: &Hash32) -> Result<Option<Node>, Box<dyn std::error::Error>>;
+    fn put_node(&self, height: usize, prefix: &Hash32, node: &Node) -> Result<(), Box<dyn std::error::Error>>;
+    fn delete_node(&self, height: usize, prefix: &Hash32) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+pub fn hash_pair(left: &Hash32, right: &Hash32) -> Hash32 {
+    if left == &ZERO_HASH && right == &ZERO_HASH {
+        return ZERO_HASH;
+    }
+    keccak::hashv(&[left, right]).to_bytes()
+}
+
+pub fn leaf_hash(pubkey: &Pubkey, account: &AccountFileData) -> Hash32 {
+    keccak::hashv(&[
+        pubkey.as_ref(),
+        account.owner.as_ref(),
+        &account.lamports.to_be_bytes(),
+        &account.data,
+    ])
+    .to_bytes()
+}
+
+/// The bit of the key that selects the child of a node at `height`, 1 is right.
+pub fn path_bit(key: &Hash32, height: usize) -> u8 {
+    let depth = TREE_HEIGHT - height;
+    (key[depth / 8] >> (7 - depth % 8)) & 1
+}
+
+pub fn prefix(key: &Hash32, height: usize) -> Hash32 {
+    let mut prefix = *key;
+    for bit in 0..height {
+        let depth = TREE_HEIGHT - 1 - bit;
+        prefix[depth / 8] &= !(1 << (7 - depth % 8));
+    }
+    prefix
+}
+
+fn sibling_prefix(key: &Hash32, height: usize) -> Hash32 {
+    let mut sibling = prefix(key, height - 1);
+    let depth = TREE_HEIGHT - height;
+    sibling[depth / 8] ^= 1 << (7 - depth % 8);
+    sibling
+}
+
+/// The hash of the subtree at `to_height` that has a single leaf.
+pub fn lift(key: &Hash32, hash: &Hash32, from_height: usize, to_height: usize) -> Hash32 {
+    let mut hash = *hash;
+    for height in from_height..to_height {
+        hash = match path_bit(key, height + 1) {
+            0 => hash_pair(&hash, &ZERO_HASH),
+            _ => hash_pair(&ZERO_HASH, &hash),
+        };
+    }
+    hash
+}
+
+pub fn node_hash(node: &Option<Node>, height: usize) -> Hash32 {
+    match node {
+        None => ZERO_HASH,
+        Some(Node::Branch { hash }) => *hash,
+        Some(Node::Leaf { key, hash }) => lift(&key.to_bytes(), hash, 0, height),
+    }
+}
+
+pub fn root<S: NodeStore + ?Sized>(store: &S) -> Result<Hash32, Box<dyn std::error::Error>> {
+    let node = store.get_node(TREE_HEIGHT, &ZERO_HASH)?;
+    Ok(node_hash(&node, TREE_HEIGHT))
+}
+
+/// Sets the leaf of the account, `None` removes it.
+pub fn update<S: NodeStore + ?Sized>(
+    store: &S,
+    key: &Pubkey,
+    leaf: Option<Hash32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    update_node(store, TREE_HEIGHT, key, leaf)?;
+    Ok(())
+}
+
+fn update_node<S: NodeStore + ?Sized>(
+    store: &S,
+    height: usize,
+    key: &Pubkey,
+    leaf: Option<Hash32>,
+) -> Result<Option<Node>, Box<dyn std::error::Error>> {
+    let node_prefix = prefix(&key.to_bytes(), height);
+    let current = store.get_node(height, &node_prefix)?;
+    let new = match current {
+        None => leaf.map(|hash| Node::Leaf { key: *key, hash }),
+        Some(Node::Leaf { key: other, .. }) if other == *key => leaf.map(|hash| Node::Leaf { key: *key, hash }),
+        Some(Node::Leaf { .. }) if leaf.is_none() => return Ok(current),
+        Some(Node::Leaf { key: other, hash: other_hash }) => {
+            // two accounts now, the other one goes down a level
+            let other_prefix = prefix(&other.to_bytes(), height - 1);
+            let other_leaf = Node::Leaf { key: other, hash: other_hash };
+            store.put_node(height - 1, &other_prefix, &other_leaf)?;
+            update_branch(store, height, key, leaf)?
+        }
+        Some(Node::Branch { .. }) => update_branch(store, height, key, leaf)?,
+    };
+    match &new {
+        Some(node) => store.put_node(height, &node_prefix, node)?,
+        None => store.delete_node(height, &node_prefix)?,
+    }
+    Ok(new)
+}
+
+fn update_branch<S: NodeStore + ?Sized>(
+    store: &S,
+    height: usize,
+    key: &Pubkey,
+    leaf: Option<Hash32>,
+) -> Result<Option<Node>, Box<dyn std::error::Error>> {
+    let key_bytes = key.to_bytes();
+    let child = update_node(store, height - 1, key, leaf)?;
+    let sibling_prefix = sibling_prefix(&key_bytes, height);
+    let sibling = store.get_node(height - 1, &sibling_prefix)?;
+    match (&child, &sibling) {
+        (None, None) => Ok(None),
+        // a single account left, it goes up a level
+        (Some(Node::Leaf { .. }), None) => {
+            store.delete_node(height - 1, &prefix(&key_bytes, height - 1))?;
+            Ok(child)
+        }
+        (None, Some(Node::Leaf { .. })) => {
+            store.delete_node(height - 1, &sibling_prefix)?;
+            Ok(sibling)
+        }
+        _ => {
+            let child_hash = node_hash(&child, height - 1);
+            let sibling_hash = node_hash(&sibling, height - 1);
+            let hash = match path_bit(&key_bytes, height) {
+                0 => hash_pair(&child_hash, &sibling_hash),
+                _ => hash_pair(&sibling_hash, &child_hash),
+            };
+            Ok(Some(Node::Branch { hash }))
+        }
+    }
+}
+
+/// The siblings along the path of a key, enough to compute the root from its leaf.
+///
+/// Bit `i` of `bitmap`, read as a big endian `uint256`, is set when the
+/// sibling at height `i` isn't zero, and `siblings` only has those, from the
+/// bottom up. On Ethereum:
+///
+/// ```text
+/// bytes32 node = leaf; // zero to prove the key isn't there
+/// uint256 next = 0;
+/// for (uint256 i = 0; i < 256; i++) {
+///     bytes32 sibling = (bitmap >> i) & 1 == 1 ? siblings[next++] : bytes32(0);
+///     bool right = (uint256(key) >> i) & 1 == 1;
+///     (bytes32 left, bytes32 right_) = right ? (sibling, node) : (node, sibling);
+///     node = left == 0 && right_ == 0 ? bytes32(0) : keccak256(abi.encodePacked(left, right_));
+/// }
+/// require(node == root);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Proof {
+    pub bitmap: Hash32,
+    pub siblings: Vec<Hash32>,
+}
+
+impl Proof {
+    /// The root of the tree where the key has this leaf, `None` if the proof is malformed.
+    pub fn compute_root(&self, key: &Pubkey, leaf: &Hash32) -> Option<Hash32> {
+        let key_bytes = key.to_bytes();
+        let mut siblings = self.siblings.iter();
+        let mut node = *leaf;
+        for height in 0..TREE_HEIGHT {
+            let sibling = match (self.bitmap[31 - height / 8] >> (height % 8)) & 1 {
+                1 => *siblings.next()?,
+                _ => ZERO_HASH,
+            };
+            node = match path_bit(&key_bytes, height + 1) {
+                0 => hash_pair(&node, &sibling),
+                _ => hash_pair(&sibling, &node),
+            };
+        }
+        if siblings.next().is_some() {
+            return None;
+        }
+        Some(node)
+    }
+}
+
+/// Proves the leaf of the key, or that there is none.
+pub fn prove<S: NodeStore + ?Sized>(store: &S, key: &Pubkey) -> Result<Proof, Box<dyn std::error::Error>> {
+    let key_bytes = key.to_bytes();
+    // from the root down, the sibling at height - 1
+    let mut path_siblings: Vec<Hash32> = vec![];
+    let mut node = store.get_node(TREE_HEIGHT, &ZERO_HASH)?;
+    for height in (1..=TREE_HEIGHT).rev() {
+        match node {
+            Some(Node::Branch { .. }) => {
+                let sibling = store.get_node(height - 1, &sibling_prefix(&key_bytes, height))?;
+                path_siblings.push(node_hash(&sibling, height - 1));
+                node = store.get_node(height - 1, &prefix(&key_bytes, height - 1))?;
+            }
+            // the other account is alone in the subtree, until the paths split
+            Some(Node::Leaf { key: other, hash }) if other != *key => {
+                let other_bytes = other.to_bytes();
+                if path_bit(&other_bytes, height) == path_bit(&key_bytes, height) {
+                    path_siblings.push(ZERO_HASH);
+                } else {
+                    path_siblings.push(lift(&other_bytes, &hash, 0, height - 1));
+                    node = None;
+                }
+            }
+            _ => path_siblings.push(ZERO_HASH),
+        }
+    }
+
+    let mut proof = Proof {
+        bitmap: ZERO_HASH,
+        siblings: vec![],
+    };
+    for (height, sibling) in path_siblings.iter().rev().enumerate() {
+        if sibling != &ZERO_HASH {
+            proof.bitmap[31 - height / 8] |= 1 << (height % 8);
+            proof.siblings.push(*sibling);
+        }
+    }
+    Ok(proof)
+}
@@ -0,0 +1,189 @@
+/// Built-in System Program
+///
+/// Creates accounts, allocates their data, assigns them to programs and
+/// transfers lamports, with the checks of Solana, so programs (e.g. Anchor's
+/// `init`) can call it through CPI without a binary in `SOLANA_BIN_PATH`.
+/// The durable nonce instructions aren't supported, there are no blockhashes.
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction::{SystemError, SystemInstruction, MAX_PERMITTED_DATA_LENGTH},
+};
+
+pub use solana_program::system_program::{check_id, id};
+
+use crate::{account_manager, log_collector, owner_manager};
+
+fn system_error(error: SystemError) -> ProgramError {
+    ProgramError::Custom(error as u32)
+}
+
+pub fn process_instruction(_program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let instruction: SystemInstruction =
+        bincode::deserialize(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+    match instruction {
+        SystemInstruction::CreateAccount { lamports, space, owner } => {
+            let [from_info, to_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_signer(to_info)?;
+            create_account(from_info, to_info, lamports, space, &owner)
+        }
+        SystemInstruction::CreateAccountWithSeed { base, seed, lamports, space, owner } => {
+            let [from_info, to_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_seed_address(to_info.key, &base, &seed, &owner)?;
+            check_base_signer(accounts, &base)?;
+            create_account(from_info, to_info, lamports, space, &owner)
+        }
+        SystemInstruction::Assign { owner } => {
+            let [account_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_signer(account_info)?;
+            assign(account_info, &owner)
+        }
+        SystemInstruction::AssignWithSeed { base, seed, owner } => {
+            let [account_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_seed_address(account_info.key, &base, &seed, &owner)?;
+            check_base_signer(accounts, &base)?;
+            assign(account_info, &owner)
+        }
+        SystemInstruction::Allocate { space } => {
+            let [account_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_signer(account_info)?;
+            allocate(account_info, space)
+        }
+        SystemInstruction::AllocateWithSeed { base, seed, space, owner } => {
+            let [account_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_seed_address(account_info.key, &base, &seed, &owner)?;
+            check_base_signer(accounts, &base)?;
+            allocate(account_info, space)?;
+            assign(account_info, &owner)
+        }
+        SystemInstruction::Transfer { lamports } => {
+            let [from_info, to_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            process_transfer(from_info, to_info, lamports)
+        }
+        SystemInstruction::TransferWithSeed { lamports, from_seed, from_owner } => {
+            let [from_info, base_info, to_info, ..] = accounts else {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            };
+            check_signer(base_info)?;
+            check_seed_address(from_info.key, base_info.key, &from_seed, &from_owner)?;
+            transfer(from_info, to_info, lamports)
+        }
+        _ => {
+            log_collector::log("Durable nonce instructions are not supported");
+            Err(ProgramError::InvalidInstructionData)
+        }
+    }
+}
+
+fn check_signer(account_info: &AccountInfo) -> ProgramResult {
+    if !account_info.is_signer {
+        log_collector::log(&format!("{} must sign", account_info.key));
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    Ok(())
+}
+
+/// The base of a `*WithSeed` instruction must sign, it can be any of the accounts.
+fn check_base_signer(accounts: &[AccountInfo], base: &Pubkey) -> ProgramResult {
+    if accounts.iter().any(|account| account.key == base && account.is_signer) {
+        return Ok(());
+    }
+    log_collector::log(&format!("{} must sign", base));
+    Err(ProgramError::MissingRequiredSignature)
+}
+
+fn check_seed_address(address: &Pubkey, base: &Pubkey, seed: &str, owner: &Pubkey) -> ProgramResult {
+    if address != &Pubkey::create_with_seed(base, seed, owner)? {
+        log_collector::log(&format!("{} doesn't match the address derived from the seed", address));
+        return Err(system_error(SystemError::AddressWithSeedMismatch));
+    }
+    Ok(())
+}
+
+fn create_account(
+    from_info: &AccountInfo,
+    to_info: &AccountInfo,
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+) -> ProgramResult {
+    // an account with lamports exists already, even without data
+    if to_info.lamports() > 0 {
+        log_collector::log(&format!("Create Account: account {} already in use", to_info.key));
+        return Err(system_error(SystemError::AccountAlreadyInUse));
+    }
+    allocate(to_info, space)?;
+    assign(to_info, owner)?;
+    check_signer(from_info)?;
+    transfer(from_info, to_info, lamports)
+}
+
+fn allocate(account_info: &AccountInfo, space: u64) -> ProgramResult {
+    if !account_info.data_is_empty() || !check_id(account_info.owner) {
+        log_collector::log(&format!("Allocate: account {} already in use", account_info.key));
+        return Err(system_error(SystemError::AccountAlreadyInUse));
+    }
+    if space > MAX_PERMITTED_DATA_LENGTH {
+        log_collector::log(&format!("Allocate: requested {}, max allowed {}", space, MAX_PERMITTED_DATA_LENGTH));
+        return Err(system_error(SystemError::InvalidAccountDataLength));
+    }
+    account_manager::set_data_size(account_info, space as usize);
+    Ok(())
+}
+
+/// Only the accounts of the System Program can be assigned to another one.
+fn assign(account_info: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+    if account_info.owner == owner {
+        return Ok(());
+    }
+    if !check_id(account_info.owner) {
+        log_collector::log(&format!("Assign: account {} is not owned by the System Program", account_info.key));
+        return Err(ProgramError::IllegalOwner);
+    }
+    owner_manager::change_owner(*account_info.key, *owner);
+    Ok(())
+}
+
+/// The `Transfer` instruction, also for the built-in programs that fund an
+/// account, which go through the System Program on Solana.
+pub(crate) fn process_transfer(from_info: &AccountInfo, to_info: &AccountInfo, lamports: u64) -> ProgramResult {
+    check_signer(from_info)?;
+    transfer(from_info, to_info, lamports)
+}
+
+/// Only the accounts of the System Program without data can be debited.
+fn transfer(from_info: &AccountInfo, to_info: &AccountInfo, lamports: u64) -> ProgramResult {
+    if !from_info.data_is_empty() {
+        log_collector::log("Transfer: `from` must not carry data");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !check_id(from_info.owner) {
+        log_collector::log("Transfer: `from` must be owned by the System Program");
+        return Err(ProgramError::InvalidAccountOwner);
+    }
+    let from_lamports = from_info.lamports();
+    if lamports > from_lamports {
+        log_collector::log(&format!("Transfer: insufficient lamports {}, need {}", from_lamports, lamports));
+        return Err(system_error(SystemError::ResultWithNegativeLamports));
+    }
+    **from_info.try_borrow_mut_lamports()? = from_lamports - lamports;
+    let to_lamports = to_info.lamports().checked_add(lamports).ok_or(ProgramError::ArithmeticOverflow)?;
+    **to_info.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
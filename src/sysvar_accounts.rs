@@ -0,0 +1,92 @@
+/// Sysvar accounts
+///
+/// Programs may take a sysvar as an account and read it with
+/// `Clock::from_account_info`, so the known sysvars are synthesized from the
+/// same values of the `CartesiStubs` syscalls instead of being loaded from
+/// the account store. They are never persisted.
+use serde::Serialize;
+#[allow(deprecated)]
+use solana_program::sysvar::{
+    clock::Clock, epoch_rewards::EpochRewards, epoch_schedule::EpochSchedule, fees::Fees,
+    last_restart_slot::LastRestartSlot, rent::Rent, slot_hashes::SlotHashes, Sysvar,
+};
+use solana_program::{
+    instruction::CompiledInstruction,
+    pubkey::Pubkey,
+    sysvar::{
+        self,
+        instructions::{self, BorrowedAccountMeta, BorrowedInstruction},
+    },
+};
+
+use crate::{address_lookup_table, cartesi_stub};
+
+/// The Instructions sysvar of a transaction, its data depends on the
+/// instruction being executed: see `set_current_instruction`.
+pub fn load_instructions_account(
+    account_keys: &[Pubkey],
+    instructions: &[CompiledInstruction],
+    writable: &[bool],
+    is_signer: &[bool],
+) -> (Vec<u8>, u64, Pubkey) {
+    let borrowed_instructions: Vec<BorrowedInstruction> = instructions
+        .iter()
+        .map(|instruction| BorrowedInstruction {
+            program_id: &account_keys[instruction.program_id_index as usize],
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|index| BorrowedAccountMeta {
+                    pubkey: &account_keys[*index as usize],
+                    is_signer: is_signer[*index as usize],
+                    is_writable: writable[*index as usize],
+                })
+                .collect(),
+            data: &instruction.data,
+        })
+        .collect();
+    let data = instructions::construct_instructions_data(&borrowed_instructions);
+    let lamports = cartesi_stub::current_rent().minimum_balance(data.len()).max(1);
+    (data, lamports, sysvar::id())
+}
+
+pub fn set_current_instruction(data: &mut [u8], instruction_index: usize) {
+    instructions::store_current_index(data, instruction_index as u16);
+}
+
+/// Data, lamports and owner of the sysvar account, `None` for other keys.
+pub fn load_sysvar_account(key: &Pubkey) -> Option<(Vec<u8>, u64, Pubkey)> {
+    #[allow(deprecated)]
+    let data = if sysvar::clock::check_id(key) {
+        serialize::<Clock>(&cartesi_stub::current_clock())
+    } else if sysvar::rent::check_id(key) {
+        serialize::<Rent>(&cartesi_stub::current_rent())
+    } else if sysvar::epoch_schedule::check_id(key) {
+        serialize::<EpochSchedule>(&cartesi_stub::current_epoch_schedule())
+    } else if sysvar::fees::check_id(key) {
+        serialize::<Fees>(&Fees::default())
+    } else if sysvar::epoch_rewards::check_id(key) {
+        serialize::<EpochRewards>(&EpochRewards::default())
+    } else if sysvar::last_restart_slot::check_id(key) {
+        serialize::<LastRestartSlot>(&LastRestartSlot::default())
+    } else if sysvar::slot_hashes::check_id(key) {
+        let current_slot = cartesi_stub::current_clock().slot;
+        serialize::<SlotHashes>(&address_lookup_table::recent_slot_hashes(current_slot))
+    } else {
+        return None;
+    };
+    let lamports = cartesi_stub::current_rent().minimum_balance(data.len()).max(1);
+    Some((data, lamports, sysvar::id()))
+}
+
+/// Any sysvar, even the ones that aren't synthesized.
+pub fn is_sysvar(key: &Pubkey) -> bool {
+    sysvar::is_sysvar_id(key)
+}
+
+/// The bincode contents, in an account of the size of the sysvar.
+fn serialize<S: Sysvar + Serialize>(value: &S) -> Vec<u8> {
+    let mut data = bincode::serialize(value).unwrap();
+    data.resize(data.len().max(S::size_of()), 0);
+    data
+}
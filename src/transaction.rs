@@ -2,14 +2,37 @@
  * Copy from solana just to deserialize de input
  */
 use generic_array::{typenum::U64, GenericArray};
-use solana_program::message::Message;
+use solana_program::message::{
+    v0::{LoadedAddresses, LoadedMessage},
+    Message, VersionedMessage,
+};
 use serde::Serialize;
 use serde::Deserialize;
 use solana_program::short_vec;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
 
 #[derive(Serialize, Deserialize)]
 pub struct Signature(GenericArray<u8, U64>);
 
+impl Signature {
+    /// Inputs coming from Ethereum users carry zeroed placeholder signatures.
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|byte| *byte == 0)
+    }
+
+    pub fn verify(&self, pubkey: &Pubkey, message: &[u8]) -> bool {
+        let pubkey = match ed25519_dalek::PublicKey::from_bytes(pubkey.as_ref()) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        match ed25519_dalek::Signature::from_bytes(&self.0[..]) {
+            Ok(signature) => pubkey.verify_strict(message, &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Transaction {
     /// A set of signatures of a serialized [`Message`], signed by the first
@@ -27,3 +50,59 @@ pub struct Transaction {
     /// The message to sign.
     pub message: Message,
 }
+
+/// Same wire format as [`Transaction`], but the message may be either a legacy
+/// or a v0 message. Legacy payloads decode into [`VersionedMessage::Legacy`].
+#[derive(Serialize, Deserialize)]
+pub struct VersionedTransaction {
+    #[serde(with = "short_vec")]
+    pub signatures: Vec<Signature>,
+
+    /// The message to sign.
+    pub message: VersionedMessage,
+}
+
+impl From<Transaction> for VersionedTransaction {
+    fn from(tx: Transaction) -> Self {
+        Self {
+            signatures: tx.signatures,
+            message: VersionedMessage::Legacy(tx.message),
+        }
+    }
+}
+
+impl VersionedTransaction {
+    /// Whether the account at `index` of the static keys followed by the
+    /// `loaded` addresses is writable, according to the message header.
+    /// Program ids and reserved keys are demoted to readonly like on mainnet.
+    pub fn is_writable(&self, index: usize, loaded: &LoadedAddresses) -> bool {
+        match &self.message {
+            VersionedMessage::Legacy(message) => message.is_writable(index),
+            VersionedMessage::V0(message) => LoadedMessage::new_borrowed(message, loaded).is_writable(index),
+        }
+    }
+
+    /// Verifies the ed25519 signatures against the serialized message and
+    /// returns the keys that signed it. Placeholder (zeroed) signatures are
+    /// skipped, any other signature that doesn't match rejects the transaction.
+    pub fn verify_signatures(&self) -> Result<Vec<Pubkey>, ProgramError> {
+        let message = self.message.serialize();
+        let account_keys = self.message.static_account_keys();
+        let num_required_signatures = self.message.header().num_required_signatures as usize;
+        let mut signers = vec![];
+        for (i, signature) in self.signatures.iter().enumerate() {
+            if signature.is_empty() {
+                continue;
+            }
+            let key = match account_keys.get(i) {
+                Some(key) if i < num_required_signatures => key,
+                _ => return Err(ProgramError::InvalidArgument),
+            };
+            if !signature.verify(key, &message) {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            signers.push(key.to_owned());
+        }
+        Ok(signers)
+    }
+}
@@ -1,30 +1,19 @@
-use std::{time::{SystemTime, UNIX_EPOCH}, fs, str::FromStr, cell::RefCell, rc::Rc};
+mod common;
 
-use cartesi_solana::{owner_manager, account_manager::{create_account_manager, AccountFileData, self}};
+use std::{fs, str::FromStr, cell::RefCell, rc::Rc, sync::{Arc, MutexGuard}};
+
+use cartesi_solana::{account_manager::{create_account_manager, AccountFileData, AccountStore, DataSlice, MemoryAccountStore, ProgramAccountsFilter, self}};
 use solana_program::{pubkey::Pubkey, account_info::AccountInfo};
 
-fn setup() {
-    println!("\n\n***** setup *****\n");
-    let dir = std::env::temp_dir();
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let final_temp_dir = format!(
-        "{}/{}",
-        dir.as_os_str().to_str().unwrap(),
-        since_the_epoch.subsec_nanos()
-    );
-    println!("{}", final_temp_dir);
-    fs::create_dir(&final_temp_dir).unwrap();
-    std::env::set_var("SOLANA_DATA_PATH", final_temp_dir);
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup_data_path();
     std::env::set_var("PORTAL_ADDRESS", "0xf8c694fd58360de278d5ff2276b7130bfdc0192a");
-    owner_manager::clear();
+    guard
 }
 
 #[test]
 fn it_should_write_read_and_delete_an_account() {
-    setup();
+    let _guard = setup();
     let account_manager = create_account_manager();
     let pubkey = Pubkey::default();
     let data = vec![];
@@ -62,7 +51,7 @@ fn it_should_write_read_and_delete_an_account() {
 
 #[test]
 fn it_should_list_all_program_accounts() {
-    setup();
+    let _guard = setup();
     let account_manager = create_account_manager();
     let pubkey = Pubkey::default();
 
@@ -85,8 +74,122 @@ fn it_should_list_all_program_accounts() {
 }
 
 
+#[test]
+fn it_should_list_program_accounts_sorted_and_skip_other_files() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let account_manager = create_account_manager();
+    let owner = Pubkey::new_unique();
+    let other_owner = Pubkey::new_unique();
+    let mut keys: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).rev().collect();
+    for key in keys.iter() {
+        let account_file_data = AccountFileData { owner, data: vec![], lamports: 1 };
+        account_manager.write_account(key, &account_file_data).unwrap();
+    }
+    fs::write(format!("{}/return_data.out", data_path), "not an account").unwrap();
+    fs::write(format!("{}/{}.json", data_path, Pubkey::new_unique()), "{").unwrap();
+
+    // moving an account to another owner updates the index
+    let moved = keys.pop().unwrap();
+    let account_file_data = AccountFileData { owner: other_owner, data: vec![], lamports: 1 };
+    account_manager.write_account(&moved, &account_file_data).unwrap();
+    let deleted = keys.pop().unwrap();
+    account_manager.delete_account(&deleted).unwrap();
+
+    keys.sort();
+    let account_files = account_manager.find_program_accounts(&owner).unwrap();
+    let found: Vec<Pubkey> = account_files.iter().map(|(key, _)| *key).collect();
+    assert_eq!(found, keys);
+    let account_files = account_manager.find_program_accounts(&other_owner).unwrap();
+    assert_eq!(account_files.len(), 1);
+    assert_eq!(account_files[0].0, moved);
+}
+
+#[test]
+fn it_should_index_accounts_saved_before_the_owner_index() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let owner = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+    let account_file_data = AccountFileData { owner, data: vec![], lamports: 1 };
+    let contents = serde_json::to_string(&account_file_data).unwrap();
+    fs::write(format!("{}/{}.json", data_path, key), contents).unwrap();
+
+    let account_files = create_account_manager().find_program_accounts(&owner).unwrap();
+    assert_eq!(account_files.len(), 1);
+    assert_eq!(account_files[0].0, key);
+}
+
+#[test]
+fn it_should_keep_accounts_in_memory() {
+    let _guard = setup();
+    account_manager::set_account_store(Arc::new(MemoryAccountStore::new()));
+    let account_manager = create_account_manager();
+    let owner = Pubkey::default();
+    let key = Pubkey::from_str("EwiqbApgaLT2kQaohqZnSXT9HbkMQWDektXEjXGMJyJv").unwrap();
+    let account_file_data_to_write = AccountFileData {
+        owner,
+        data: vec![1, 2, 3],
+        lamports: 1234u64,
+    };
+    account_manager.write_account(&key, &account_file_data_to_write).unwrap();
+
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    assert_eq!(fs::read_dir(data_path).unwrap().count(), 0);
+
+    let account_file_data = create_account_manager().read_account(&key).unwrap();
+    assert_eq!(account_file_data.data, vec![1, 2, 3]);
+    let account_files = account_manager.find_program_accounts(&owner).unwrap();
+    assert_eq!(account_files.len(), 1);
+    assert_eq!(account_files[0].0, key);
+
+    account_manager.delete_account(&key).unwrap();
+    let read_result = account_manager.read_account(&key);
+    assert!(read_result.is_err());
+    account_manager::reset_account_store();
+}
+
+#[test]
+fn it_should_filter_program_accounts_like_the_rpc() {
+    let _guard = setup();
+    let mint = Pubkey::new_unique();
+    let other_mint = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let token_account = |mint: &Pubkey, size: usize| {
+        let mut data = vec![7u8; size];
+        data[..32].copy_from_slice(mint.as_ref());
+        AccountFileData { owner, data, lamports: 1 }
+    };
+    let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let memory_store: Arc<dyn account_manager::AccountStore> = Arc::new(MemoryAccountStore::new());
+    for account_manager in [create_account_manager(), memory_store] {
+        account_manager.write_account(&keys[0], &token_account(&mint, 165)).unwrap();
+        account_manager.write_account(&keys[1], &token_account(&other_mint, 165)).unwrap();
+        account_manager.write_account(&keys[2], &token_account(&mint, 82)).unwrap();
+
+        let filters = vec![
+            ProgramAccountsFilter::DataSize(165),
+            ProgramAccountsFilter::Memcmp { offset: 0, bytes: mint.to_bytes().to_vec() },
+        ];
+        let data_slice = DataSlice { offset: 32, length: 4 };
+        let accounts = account_manager
+            .find_program_accounts_with_filters(&owner, &filters, Some(data_slice))
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].0, keys[0]);
+        assert_eq!(accounts[0].1.data, vec![7u8; 4]);
+
+        let filters = vec![ProgramAccountsFilter::Memcmp { offset: 164, bytes: vec![7, 7] }];
+        let accounts = account_manager
+            .find_program_accounts_with_filters(&owner, &filters, None)
+            .unwrap();
+        assert!(accounts.is_empty());
+    }
+}
+
 #[test]
 fn it_should_set_data_size() {
+    let _guard = common::lock();
     let owner: Pubkey = Pubkey::default();
     let key = &Pubkey::default();
     let mut lamports: u64 = 1000;
@@ -103,4 +206,172 @@ fn it_should_set_data_size() {
     };
     account_manager::set_data_size(&account_info, 10);
     assert_eq!(account_info.data.borrow().len(), 10);
-}
\ No newline at end of file
+}
+#[test]
+fn it_should_restore_a_snapshot() {
+    let _guard = setup();
+    let file_store = account_manager::create_file_account_manager();
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let created = Pubkey::new_unique();
+    file_store.write_account(&key, &AccountFileData { owner, data: vec![1], lamports: 10 }).unwrap();
+    let root = file_store.state_root().unwrap();
+    let snapshot = file_store.create_snapshot("initial").unwrap();
+    assert_eq!(snapshot.state_root, root);
+    assert!(file_store.create_snapshot("initial").is_err());
+    assert!(file_store.create_snapshot("../initial").is_err());
+
+    file_store.write_account(&key, &AccountFileData { owner, data: vec![2], lamports: 20 }).unwrap();
+    file_store.write_account(&created, &AccountFileData { owner, data: vec![], lamports: 1 }).unwrap();
+    assert_ne!(file_store.state_root().unwrap(), root);
+
+    file_store.restore_snapshot("initial").unwrap();
+    let account_file_data = file_store.read_account(&key).unwrap();
+    assert_eq!(account_file_data.data, vec![1]);
+    assert_eq!(account_file_data.lamports, 10);
+    assert!(file_store.read_account(&created).is_err());
+    assert_eq!(file_store.find_program_accounts(&owner).unwrap().len(), 1);
+    assert_eq!(file_store.state_root().unwrap(), root);
+    assert!(file_store.restore_snapshot("missing").is_err());
+}
+
+#[test]
+fn it_should_finish_an_interrupted_restore() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let file_store = account_manager::create_file_account_manager();
+    let key = Pubkey::new_unique();
+    let account_file_data = AccountFileData { owner: Pubkey::new_unique(), data: vec![], lamports: 10 };
+    file_store.write_account(&key, &account_file_data).unwrap();
+    file_store.create_snapshot("before").unwrap();
+    file_store.delete_account(&key).unwrap();
+
+    // crashed right after deciding to restore
+    fs::write(format!("{}/.snapshots/.restore", data_path), "before").unwrap();
+    let file_store = account_manager::create_file_account_manager();
+    assert_eq!(file_store.read_account(&key).unwrap().lamports, 10);
+    assert!(!std::path::Path::new(&format!("{}/.snapshots/.restore", data_path)).exists());
+}
+
+#[test]
+fn it_should_only_snapshot_and_restore_the_accounts() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let file_store = account_manager::create_file_account_manager();
+    let key = Pubkey::new_unique();
+    let account_file_data = AccountFileData { owner: Pubkey::new_unique(), data: vec![], lamports: 10 };
+    file_store.write_account(&key, &account_file_data).unwrap();
+    fs::write(format!("{}/program.so", data_path), "program").unwrap();
+    fs::create_dir(format!("{}/logs", data_path)).unwrap();
+    fs::write(format!("{}/logs/input.log", data_path), "log").unwrap();
+
+    file_store.create_snapshot("accounts").unwrap();
+    let snapshot_data_path = format!("{}/.snapshots/accounts/data", data_path);
+    assert!(std::path::Path::new(&format!("{}/{}.json", snapshot_data_path, key)).exists());
+    assert!(std::path::Path::new(&format!("{}/.owners", snapshot_data_path)).exists());
+    assert!(std::path::Path::new(&format!("{}/.state", snapshot_data_path)).exists());
+    assert!(!std::path::Path::new(&format!("{}/program.so", snapshot_data_path)).exists());
+    assert!(!std::path::Path::new(&format!("{}/logs", snapshot_data_path)).exists());
+
+    file_store.delete_account(&key).unwrap();
+    file_store.restore_snapshot("accounts").unwrap();
+    assert_eq!(file_store.read_account(&key).unwrap().lamports, 10);
+    assert_eq!(fs::read_to_string(format!("{}/program.so", data_path)).unwrap(), "program");
+    assert_eq!(fs::read_to_string(format!("{}/logs/input.log", data_path)).unwrap(), "log");
+}
+
+#[test]
+fn it_should_not_snapshot_the_working_directory() {
+    let _guard = common::lock();
+    for base_path in ["./", ".", ""] {
+        let mut file_store = account_manager::AccountManager::new().unwrap();
+        file_store.set_base_path(base_path.to_string());
+        assert!(file_store.create_snapshot("cwd").is_err());
+        assert!(file_store.restore_snapshot("cwd").is_err());
+    }
+    assert!(!std::path::Path::new(".snapshots").exists());
+}
+
+#[test]
+fn it_should_list_and_prune_snapshots() {
+    let _guard = setup();
+    let file_store = account_manager::create_file_account_manager();
+    for name in ["a", "b", "c"] {
+        file_store.create_snapshot(name).unwrap();
+    }
+    let names = |snapshots: Vec<account_manager::SnapshotInfo>| -> Vec<String> {
+        snapshots.into_iter().map(|snapshot| snapshot.name).collect()
+    };
+    assert_eq!(names(file_store.list_snapshots().unwrap()), vec!["a", "b", "c"]);
+
+    assert_eq!(names(file_store.prune_snapshots(1).unwrap()), vec!["a", "b"]);
+    assert_eq!(names(file_store.list_snapshots().unwrap()), vec!["c"]);
+    file_store.delete_snapshot("c").unwrap();
+    assert!(file_store.list_snapshots().unwrap().is_empty());
+}
+
+#[test]
+fn it_should_commit_the_accounts_of_an_input() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let account_manager = create_account_manager();
+    let owner = Pubkey::new_unique();
+    let deleted = Pubkey::new_unique();
+    let saved = Pubkey::new_unique();
+    account_manager.write_account(&deleted, &AccountFileData { owner, data: vec![], lamports: 1 }).unwrap();
+
+    let writes = vec![
+        (deleted, None),
+        (saved, Some(AccountFileData { owner, data: vec![1, 2], lamports: 5 })),
+    ];
+    account_manager.commit_accounts(&writes).unwrap();
+    assert!(account_manager.read_account(&deleted).is_err());
+    assert_eq!(account_manager.read_account(&saved).unwrap().data, vec![1, 2]);
+    assert!(!std::path::Path::new(&format!("{}/.journal.json", data_path)).exists());
+}
+
+#[test]
+fn it_should_recover_an_interrupted_commit() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let owner = Pubkey::new_unique();
+    let first = Pubkey::new_unique();
+    let second = Pubkey::new_unique();
+    let account_manager = create_account_manager();
+    account_manager.write_account(&second, &AccountFileData { owner, data: vec![], lamports: 1 }).unwrap();
+
+    // crashed after applying the first write of the journal
+    let writes: Vec<account_manager::AccountWrite> = vec![
+        (first, Some(AccountFileData { owner, data: vec![3], lamports: 7 })),
+        (second, None),
+    ];
+    fs::write(format!("{}/.journal.json", data_path), serde_json::to_string(&writes).unwrap()).unwrap();
+    account_manager.write_account(&first, writes[0].1.as_ref().unwrap()).unwrap();
+
+    let account_manager = create_account_manager();
+    assert_eq!(account_manager.read_account(&first).unwrap().lamports, 7);
+    assert!(account_manager.read_account(&second).is_err());
+    assert_eq!(account_manager.find_program_accounts(&owner).unwrap().len(), 1);
+    assert!(!std::path::Path::new(&format!("{}/.journal.json", data_path)).exists());
+}
+
+#[test]
+fn it_should_rebuild_the_owner_index_of_an_interrupted_commit() {
+    let _guard = setup();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let old_owner = Pubkey::new_unique();
+    let new_owner = Pubkey::new_unique();
+    let moved = Pubkey::new_unique();
+    let account_manager = create_account_manager();
+    account_manager.write_account(&moved, &AccountFileData { owner: old_owner, data: vec![], lamports: 1 }).unwrap();
+
+    // crashed after writing the account file, before updating the owner index
+    let account = AccountFileData { owner: new_owner, data: vec![], lamports: 1 };
+    let writes: Vec<account_manager::AccountWrite> = vec![(moved, Some(account.clone()))];
+    fs::write(format!("{}/.journal.json", data_path), serde_json::to_string(&writes).unwrap()).unwrap();
+    fs::write(format!("{}/{}.json", data_path, moved), serde_json::to_string(&account).unwrap()).unwrap();
+
+    account_manager::create_file_account_manager();
+    assert!(!std::path::Path::new(&format!("{}/.owners/{}/{}", data_path, old_owner, moved)).exists());
+    assert!(std::path::Path::new(&format!("{}/.owners/{}/{}", data_path, new_owner, moved)).exists());
+}
@@ -1,39 +1,23 @@
-use std::{
-    fs,
-    str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
-};
+mod common;
+
+use std::{str::FromStr, sync::MutexGuard};
 
 
 use cartesi_solana::{
     adapter::{call_smart_contract_base64, eth_address_to_pubkey, parse_processor_args, persist_accounts},
-    owner_manager, transaction::{self, Signature}, account_manager,
+    owner_manager, transaction::{self, Signature},
 };
-use solana_program::{message::{MessageHeader, Message}, pubkey::Pubkey, instruction::CompiledInstruction, account_info::AccountInfo, entrypoint::ProgramResult};
+use solana_program::{message::{v0, MessageHeader, Message, VersionedMessage}, pubkey::Pubkey, instruction::CompiledInstruction, account_info::AccountInfo, entrypoint::ProgramResult};
 use solana_program::hash::Hash;
 
 
-fn setup() {
-    println!("\n\n***** setup *****\n");
-    let dir = std::env::temp_dir();
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let final_temp_dir = format!(
-        "{}/{}",
-        dir.as_os_str().to_str().unwrap(),
-        since_the_epoch.subsec_nanos()
-    );
-    println!("{}", final_temp_dir);
-    fs::create_dir(&final_temp_dir).unwrap();
-    std::env::set_var("SOLANA_DATA_PATH", final_temp_dir);
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup_data_path();
     std::env::set_var(
         "PORTAL_ADDRESS",
         "0xf8c694fd58360de278d5ff2276b7130bfdc0192a",
     );
-    owner_manager::clear();
-    account_manager::clear();
+    guard
 }
 
 #[test]
@@ -54,7 +38,7 @@ fn it_runs() {
 
 #[test]
 fn it_should_call_the_solana_program_entry() {
-    setup();
+    let _guard = setup();
 
     let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
     let signatures = vec![signature];
@@ -112,12 +96,13 @@ fn it_should_call_the_solana_program_entry() {
     }
     let instruction_index = 0;
 
-    call_smart_contract_base64(payload, msg_sender, instruction_index, prog_entry);
+    let result = call_smart_contract_base64(payload, msg_sender, instruction_index, prog_entry);
+    assert_eq!(result, Ok(()));
 }
 
 #[test]
 fn it_should_parse_args() {
-    setup();
+    let _guard = setup();
 
     let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
     let signatures = vec![signature];
@@ -179,10 +164,61 @@ fn it_should_parse_args() {
     entry(&program_id, &accounts, &data).unwrap();
 }
 
+#[test]
+fn it_should_parse_args_from_v0_transaction() {
+    let _guard = setup();
+
+    let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
+    let header = MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: 2,
+    };
+    let account_keys = vec![
+        "1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2",
+        "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY",
+        "11111111111111111111111111111111",
+        "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv",
+    ]
+    .into_iter()
+    .map(|str_pk| Pubkey::from_str(str_pk).unwrap())
+    .collect();
+
+    let instruction = CompiledInstruction {
+        program_id_index: 3,
+        accounts: [1, 0, 2].to_vec(),
+        data: vec![1, 2, 3],
+    };
+    let message = v0::Message {
+        header,
+        account_keys,
+        recent_blockhash: Hash::default(),
+        instructions: vec![instruction],
+        address_table_lookups: vec![],
+    };
+    let transaction = transaction::VersionedTransaction {
+        signatures: vec![signature],
+        message: VersionedMessage::V0(message),
+    };
+    let payload = base64::encode(bincode::serialize(&transaction).unwrap());
+    let msg_sender = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+
+    let (program_id, accounts, data, last_instruction) = parse_processor_args(&payload, msg_sender, 0);
+    assert_eq!(
+        program_id.to_string(),
+        "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv"
+    );
+    assert_eq!(accounts.len(), 3);
+    assert_eq!(accounts[0].key.to_string(), "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY");
+    assert!(accounts[1].is_signer);
+    assert_eq!(data, vec![1, 2, 3]);
+    assert!(last_instruction);
+}
+
 
 #[test]
 fn it_should_change_the_owner_inside_entry() {
-    setup();
+    let _guard = setup();
 
     let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
     let signatures = vec![signature];
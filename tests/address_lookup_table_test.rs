@@ -0,0 +1,196 @@
+mod common;
+
+use std::{borrow::Cow, cell::RefCell, str::FromStr};
+
+use cartesi_solana::{adapter::load_account_info_data, executor::Executor};
+use common::{create_account, create_input, run_input, run_instructions, sender_pubkey, setup};
+use solana_program::{
+    address_lookup_table::{
+        self,
+        instruction::{
+            close_lookup_table, create_lookup_table_signed, deactivate_lookup_table, extend_lookup_table,
+            freeze_lookup_table,
+        },
+        state::{AddressLookupTable, LookupTableMeta},
+        AddressLookupTableAccount,
+    },
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    message::{v0, Message, VersionedMessage},
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    slot_hashes::MAX_ENTRIES,
+};
+
+#[test]
+fn it_should_create_and_extend_a_lookup_table() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    create_account(&authority, 1_000_000_000, Pubkey::default(), vec![]);
+
+    let (create_ix, table_key) = create_lookup_table_signed(authority, authority, 0);
+    assert_eq!(run_instruction(create_ix, 0), Ok(()));
+
+    let (data, lamports, owner) = load_account_info_data(&table_key);
+    assert_eq!(owner, address_lookup_table::program::id());
+    assert!(lamports > 0);
+    let table = AddressLookupTable::deserialize(&data).unwrap();
+    assert_eq!(table.meta.authority, Some(authority));
+    assert!(table.addresses.is_empty());
+
+    let new_addresses = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+    let extend_ix = extend_lookup_table(table_key, authority, Some(authority), new_addresses.clone());
+    assert_eq!(run_instruction(extend_ix, 1), Ok(()));
+
+    let (data, _, _) = load_account_info_data(&table_key);
+    let table = AddressLookupTable::deserialize(&data).unwrap();
+    assert_eq!(table.addresses.to_vec(), new_addresses);
+
+    assert_eq!(run_instruction(freeze_lookup_table(table_key, authority), 2), Ok(()));
+    let (data, _, _) = load_account_info_data(&table_key);
+    let table = AddressLookupTable::deserialize(&data).unwrap();
+    assert_eq!(table.meta.authority, None);
+}
+
+#[test]
+fn it_should_look_up_the_extended_addresses_from_a_later_input() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    create_account(&authority, 1_000_000_000, Pubkey::default(), vec![]);
+    let (create_ix, table_key) = create_lookup_table_signed(authority, authority, 0);
+    assert_eq!(run_instruction(create_ix, 0), Ok(()));
+    let writable_key = Pubkey::new_unique();
+    let readonly_key = Pubkey::new_unique();
+    let extend_ix = extend_lookup_table(table_key, authority, Some(authority), vec![readonly_key, writable_key]);
+    assert_eq!(run_instruction(extend_ix, 1), Ok(()));
+
+    let lookup_table = AddressLookupTableAccount {
+        key: table_key,
+        addresses: vec![readonly_key, writable_key],
+    };
+    let keys = run_lookup(&authority, writable_key, readonly_key, lookup_table, "12345 2 2 0");
+    assert_eq!(keys, Ok(vec![authority, writable_key, readonly_key]));
+}
+
+#[test]
+fn it_should_close_a_lookup_table_once_deactivated() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    let recipient = Pubkey::new_unique();
+    create_account(&authority, 1_000_000_000, Pubkey::default(), vec![]);
+    let (create_ix, table_key) = create_lookup_table_signed(authority, authority, 0);
+    assert_eq!(run_instruction(create_ix, 0), Ok(()));
+    let (_, table_lamports, _) = load_account_info_data(&table_key);
+
+    let deactivate_ix = deactivate_lookup_table(table_key, authority);
+    assert_eq!(run_instruction(deactivate_ix, 1), Ok(()));
+    let (data, _, _) = load_account_info_data(&table_key);
+    assert_eq!(AddressLookupTable::deserialize(&data).unwrap().meta.deactivation_slot, 2);
+
+    // still deactivating while the slot is among the recent ones
+    let close_ix = close_lookup_table(table_key, authority, recipient);
+    assert_eq!(run_instruction(close_ix.clone(), 2), Err(ProgramError::InvalidArgument));
+    assert_eq!(run_instruction(close_ix, 1 + MAX_ENTRIES as u64 + 1), Ok(()));
+    let (_, lamports, _) = load_account_info_data(&recipient);
+    assert_eq!(lamports, table_lamports);
+    let (_, lamports, _) = load_account_info_data(&table_key);
+    assert_eq!(lamports, 0);
+}
+
+#[test]
+fn it_should_reject_the_instructions_without_the_input_metadata() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    create_account(&authority, 1_000_000_000, Pubkey::default(), vec![]);
+    let (create_ix, table_key) = create_lookup_table_signed(authority, authority, 0);
+    let result = run_instructions(&[create_ix]);
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let (_, lamports, _) = load_account_info_data(&table_key);
+    assert_eq!(lamports, 0);
+}
+
+#[test]
+fn it_should_only_take_the_rent_from_a_system_account() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    create_account(&authority, 1_000_000_000, Pubkey::new_unique(), vec![]);
+    let (create_ix, _) = create_lookup_table_signed(authority, authority, 0);
+    assert_eq!(run_instruction(create_ix, 0), Err(ProgramError::InvalidAccountOwner));
+    let (_, lamports, _) = load_account_info_data(&authority);
+    assert_eq!(lamports, 1_000_000_000);
+}
+
+#[test]
+fn it_should_expand_lookups_into_the_instruction_accounts() {
+    let _guard = setup();
+    let authority = sender_pubkey();
+    let table_key = Pubkey::new_unique();
+    let writable_key = Pubkey::new_unique();
+    let readonly_key = Pubkey::new_unique();
+    let table = AddressLookupTable {
+        meta: LookupTableMeta::new(authority),
+        addresses: Cow::Owned(vec![readonly_key, writable_key]),
+    };
+    create_account(
+        &table_key,
+        1_000_000,
+        address_lookup_table::program::id(),
+        table.serialize_for_tests().unwrap(),
+    );
+
+    let lookup_table = AddressLookupTableAccount {
+        key: table_key,
+        addresses: vec![readonly_key, writable_key],
+    };
+    let keys = run_lookup(&authority, writable_key, readonly_key, lookup_table, "12345");
+    assert_eq!(keys, Ok(vec![authority, writable_key, readonly_key]));
+}
+
+//
+// Helper functions
+//
+
+/// Runs the instruction in the input `input_index`, at the slot `input_index + 1`.
+fn run_instruction(instruction: Instruction, input_index: u64) -> ProgramResult {
+    let message = Message::new(&[instruction], Some(&sender_pubkey()));
+    let timestamp_line = format!("12345 {} {} 0", input_index, input_index);
+    run_input(create_input(VersionedMessage::Legacy(message), &timestamp_line))
+}
+
+/// The accounts a program gets from an instruction with the signer, a writable
+/// and a readonly account, the last two looked up in the table.
+fn run_lookup(
+    authority: &Pubkey,
+    writable_key: Pubkey,
+    readonly_key: Pubkey,
+    lookup_table: AddressLookupTableAccount,
+    timestamp_line: &str,
+) -> Result<Vec<Pubkey>, ProgramError> {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let instruction = Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(writable_key, false),
+            AccountMeta::new_readonly(readonly_key, false),
+        ],
+        data: vec![7],
+    };
+    let message =
+        v0::Message::try_compile(authority, &[instruction], &[lookup_table], Hash::default()).unwrap();
+    assert_eq!(message.account_keys.len(), 2);
+    let mut executor = Executor::create_with_stdin(create_input(VersionedMessage::V0(message), timestamp_line));
+    let keys = RefCell::new(vec![]);
+    let result = executor.get_processor_args(|program_id, accounts, data| {
+        assert_eq!(
+            program_id.to_string(),
+            "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv"
+        );
+        assert!(accounts[0].is_signer);
+        assert_eq!(data, &[7]);
+        *keys.borrow_mut() = accounts.iter().map(|account| *account.key).collect();
+        Ok(())
+    });
+    result.map(|_| keys.into_inner())
+}
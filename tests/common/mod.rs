@@ -0,0 +1,155 @@
+//! Helpers shared by the test files, each one includes them with `mod common;`.
+#![allow(dead_code)]
+
+use cartesi_solana::{
+    account_manager::{self, create_account_manager, AccountFileData, MemoryAccountStore},
+    executor::{Executor, LineReader},
+    owner_manager,
+    transaction::{Signature, VersionedTransaction},
+};
+use solana_program::{
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    message::{Message, VersionedMessage},
+    pubkey::Pubkey,
+};
+use std::{
+    fmt::Write,
+    fs, io,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+lazy_static::lazy_static! {
+    // the account store, the owners, the logs, the return data, the stack
+    // height and the environment are global, so the tests take turns
+    static ref GLOBAL_STATE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Holds the global state until the guard is dropped.
+pub fn lock() -> MutexGuard<'static, ()> {
+    GLOBAL_STATE_LOCK.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Takes the lock, with no accounts in a `MemoryAccountStore`.
+pub fn setup() -> MutexGuard<'static, ()> {
+    println!("\n\n***** setup *****\n");
+    let guard = lock();
+    account_manager::set_account_store(Arc::new(MemoryAccountStore::new()));
+    owner_manager::clear();
+    account_manager::clear();
+    guard
+}
+
+/// Takes the lock, with the file store in a new temporary **SOLANA_DATA_PATH**.
+pub fn setup_data_path() -> MutexGuard<'static, ()> {
+    println!("\n\n***** setup *****\n");
+    let guard = lock();
+    let dir = std::env::temp_dir();
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let final_temp_dir = format!(
+        "{}/{}-{}",
+        dir.as_os_str().to_str().unwrap(),
+        std::process::id(),
+        since_the_epoch.as_nanos()
+    );
+    println!("{}", final_temp_dir);
+    fs::create_dir(&final_temp_dir).unwrap();
+    std::env::set_var("SOLANA_DATA_PATH", final_temp_dir);
+    account_manager::reset_account_store();
+    owner_manager::clear();
+    account_manager::clear();
+    guard
+}
+
+pub struct MyLineReader {
+    pub lines: Vec<String>,
+    pub current_line: usize,
+}
+
+impl MyLineReader {
+    pub fn create(lines: Vec<&str>) -> Self {
+        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+        Self {
+            lines,
+            current_line: 0,
+        }
+    }
+}
+
+impl LineReader for MyLineReader {
+    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        if self.current_line == self.lines.len() {
+            return Ok(0);
+        }
+        let line_with_ender = format!("{}\n", &self.lines[self.current_line]);
+        buf.write_str(&line_with_ender).unwrap();
+        self.current_line += 1;
+        Ok(1)
+    }
+}
+
+/// The payer of the test transactions.
+pub fn sender_pubkey() -> Pubkey {
+    Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap()
+}
+
+pub fn create_account(key: &Pubkey, lamports: u64, owner: Pubkey, data: Vec<u8>) {
+    let account_file_data = AccountFileData {
+        owner,
+        data,
+        lamports,
+    };
+    create_account_manager()
+        .write_account(key, &account_file_data)
+        .unwrap();
+}
+
+/// The base64 transaction, with a zero signature for each required signer.
+pub fn encode_transaction(message: VersionedMessage) -> String {
+    let signatures = (0..message.header().num_required_signatures)
+        .map(|_| bincode::deserialize::<Signature>(&[0; 64]).unwrap())
+        .collect();
+    let transaction = VersionedTransaction {
+        signatures,
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
+/// An input running every instruction of the message, e.g. with the
+/// timestamp line `12345` or `12345 <block> <input index> <epoch index>`.
+pub fn create_input(message: VersionedMessage, timestamp_line: &str) -> MyLineReader {
+    let payload = encode_transaction(message);
+    MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        timestamp_line,
+    ])
+}
+
+/// An input running the instructions, paid by `sender_pubkey`.
+pub fn create_stdin(instructions: &[Instruction]) -> MyLineReader {
+    let message = Message::new(instructions, Some(&sender_pubkey()));
+    create_input(VersionedMessage::Legacy(message), "12345")
+}
+
+/// Runs an input that only calls built-in programs.
+pub fn run_input(stdin: MyLineReader) -> ProgramResult {
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        panic!("built-in programs should not reach the program entrypoint");
+    });
+    owner_manager::clear();
+    account_manager::clear();
+    result
+}
+
+pub fn run_instructions(instructions: &[Instruction]) -> ProgramResult {
+    run_input(create_stdin(instructions))
+}
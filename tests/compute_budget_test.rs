@@ -0,0 +1,154 @@
+mod common;
+
+use cartesi_solana::{
+    compute_budget::{self, COMPUTATIONAL_BUDGET_EXCEEDED, MAX_COMPUTE_UNIT_LIMIT},
+    executor::Executor,
+    transaction,
+};
+use common::{setup, MyLineReader};
+use solana_program::{
+    clock::Clock,
+    compute_units::sol_remaining_compute_units,
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar::Sysvar,
+};
+use std::str::FromStr;
+
+#[test]
+fn it_should_read_the_compute_budget_instructions() {
+    let program_id = Pubkey::new_unique();
+    let limit = set_compute_unit_limit(300_000);
+    let price = set_compute_unit_price(5);
+    let instructions = [
+        (compute_budget::id(), limit.data.clone()),
+        (compute_budget::id(), price.data.clone()),
+        (program_id, vec![1]),
+    ];
+    let budget = compute_budget::compute_budget_from_instructions(instructions.iter().map(|(id, data)| (id, &data[..])));
+    assert_eq!(budget, Ok((300_000, 5)));
+
+    // 200k units per instruction by default, up to 1.4M
+    let instructions = vec![(program_id, vec![]); 3];
+    let budget = compute_budget::compute_budget_from_instructions(instructions.iter().map(|(id, data)| (id, &data[..])));
+    assert_eq!(budget, Ok((600_000, 0)));
+    let instructions = [
+        (compute_budget::id(), set_compute_unit_limit(u32::MAX).data),
+        (program_id, vec![]),
+    ];
+    let budget = compute_budget::compute_budget_from_instructions(instructions.iter().map(|(id, data)| (id, &data[..])));
+    assert_eq!(budget, Ok((MAX_COMPUTE_UNIT_LIMIT, 0)));
+}
+
+#[test]
+fn it_should_reject_invalid_compute_budget_instructions() {
+    let duplicated = [
+        (compute_budget::id(), set_compute_unit_limit(1000).data),
+        (compute_budget::id(), set_compute_unit_limit(2000).data),
+    ];
+    let budget = compute_budget::compute_budget_from_instructions(duplicated.iter().map(|(id, data)| (id, &data[..])));
+    assert_eq!(budget, Err(ProgramError::InvalidInstructionData));
+
+    let truncated = [(compute_budget::id(), vec![2, 1, 0])];
+    let budget = compute_budget::compute_budget_from_instructions(truncated.iter().map(|(id, data)| (id, &data[..])));
+    assert_eq!(budget, Err(ProgramError::InvalidInstructionData));
+}
+
+#[test]
+fn executor_should_charge_the_syscalls() {
+    let _guard = setup();
+    let payload = create_payload(1000);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|program_id, _accounts, _data| {
+        // the ComputeBudget instruction doesn't reach the program
+        assert!(!compute_budget::check_id(program_id));
+        let remaining = sol_remaining_compute_units();
+        assert_eq!(compute_budget::get_compute_meter().limit, 1000);
+        assert!(remaining < 1000 - compute_budget::COMPUTE_BUDGET_PROGRAM_UNITS);
+        msg!("a log is charged too");
+        assert!(sol_remaining_compute_units() < remaining - compute_budget::SYSCALL_BASE_COST);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_abort_a_program_out_of_compute_units() {
+    let _guard = setup();
+    let payload = create_payload(1000);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| loop {
+        msg!("looping forever");
+    });
+    assert_eq!(result, Err(COMPUTATIONAL_BUDGET_EXCEEDED));
+}
+
+#[test]
+fn executor_should_fail_a_program_out_of_compute_units_whatever_it_returns() {
+    let _guard = setup();
+    let payload = create_payload(1000);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        // the sysvars fail once the budget runs out, the program ignores it
+        while Clock::get().is_ok() {}
+        assert_eq!(Clock::get(), Err(COMPUTATIONAL_BUDGET_EXCEEDED));
+        Ok(())
+    });
+    assert_eq!(result, Err(COMPUTATIONAL_BUDGET_EXCEEDED));
+}
+
+//
+// Helper functions
+//
+
+fn set_compute_unit_limit(units: u32) -> Instruction {
+    let mut data = vec![2];
+    data.extend_from_slice(&units.to_le_bytes());
+    Instruction::new_with_bytes(compute_budget::id(), &data, vec![])
+}
+
+fn set_compute_unit_price(micro_lamports: u64) -> Instruction {
+    let mut data = vec![3];
+    data.extend_from_slice(&micro_lamports.to_le_bytes());
+    Instruction::new_with_bytes(compute_budget::id(), &data, vec![])
+}
+
+fn create_payload(compute_unit_limit: u32) -> String {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payer = Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap();
+    let account = Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
+    let instructions = [
+        set_compute_unit_limit(compute_unit_limit),
+        Instruction::new_with_bytes(program_id, &[1], vec![AccountMeta::new(account, false)]),
+    ];
+    let message = Message::new(&instructions, Some(&payer));
+    let transaction = transaction::Transaction {
+        signatures: vec![bincode::deserialize(&[0; 64]).unwrap()],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
@@ -1,7 +1,14 @@
+mod common;
+
 use std::str::FromStr;
 
-use cartesi_solana::cpi::check_signature;
-use solana_program::{pubkey::Pubkey, system_instruction::transfer};
+use cartesi_solana::{
+    account_manager, compute_budget,
+    cpi::{self, check_signature, CpiAccount, CpiResponse},
+};
+use solana_program::{
+    program::MAX_RETURN_DATA, program_error::ProgramError, pubkey::Pubkey, system_instruction::transfer,
+};
 
 #[test]
 fn cpi_it_should_verify_the_signature_success() {
@@ -15,11 +22,10 @@ fn cpi_it_should_verify_the_signature_success() {
     let instruction = transfer(&escrow_pubkey, &alice_pubkey, 1);
 
     let signer_program_id = Pubkey::default();
-    check_signature(&signer_program_id, &instruction, &[pda_signature]);
+    assert_eq!(check_signature(&signer_program_id, &instruction, &[], &[pda_signature]), Ok(()));
 }
 
 #[test]
-#[should_panic]
 fn cpi_it_should_verify_the_signature_fail() {
     let program_id = Pubkey::default();
     let alice_pubkey = Pubkey::default();
@@ -32,5 +38,86 @@ fn cpi_it_should_verify_the_signature_fail() {
 
     let signer_program_id =
         Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
-    check_signature(&signer_program_id, &instruction, &[pda_signature]);
+    let result = check_signature(&signer_program_id, &instruction, &[], &[pda_signature]);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn cpi_it_should_accept_the_signers_of_the_caller() {
+    let alice_pubkey = Pubkey::new_unique();
+    let (escrow_pubkey, bump) = Pubkey::find_program_address(&[b"escrow"], &Pubkey::default());
+    let instruction = transfer(&alice_pubkey, &escrow_pubkey, 1);
+
+    // no seeds needed, and the order of the signers doesn't matter
+    assert_eq!(check_signature(&Pubkey::default(), &instruction, &[alice_pubkey], &[]), Ok(()));
+    let result = check_signature(&Pubkey::default(), &instruction, &[], &[&[b"escrow", &[bump]]]);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn cpi_it_should_carry_the_return_data_back_to_the_caller() {
+    let _guard = common::lock();
+    let caller = Pubkey::new_unique();
+    let callee = Pubkey::new_unique();
+    cpi::reset_return_data(&caller);
+    assert_eq!(cpi::get_return_data(), None);
+
+    // what the called program sets
+    cpi::set_return_data(&callee, &[1, 2, 3]);
+    let response = cpi::parse_cpi_response(&format!(
+        "CPI Response: {}",
+        serde_json::to_string(&CpiResponse {
+            compute_units_consumed: 10,
+            logs: vec![],
+            return_data: Some((callee.to_string(), base64::encode([1, 2, 3]))),
+            accounts: vec![],
+            error: None,
+        })
+        .unwrap()
+    ))
+    .unwrap();
+
+    // the caller only sees it once the CPI returns
+    cpi::reset_return_data(&callee);
+    assert_eq!(cpi::get_return_data(), None);
+    cpi::apply_cpi_response_return_data(&response);
+    assert_eq!(cpi::get_return_data(), Some((callee, vec![1, 2, 3])));
+
+    cpi::set_return_data(&caller, &[0; MAX_RETURN_DATA]);
+    assert_eq!(cpi::get_return_data().unwrap().1.len(), MAX_RETURN_DATA);
+}
+
+#[test]
+fn cpi_it_should_limit_the_return_data() {
+    let _guard = common::lock();
+    let result = compute_budget::run_metered(|| {
+        cpi::set_return_data(&Pubkey::new_unique(), &[0; MAX_RETURN_DATA + 1]);
+        Ok(())
+    });
+    assert_eq!(result, Err(ProgramError::InvalidInstructionData));
+}
+
+#[test]
+fn cpi_it_should_give_the_accounts_of_the_called_program_to_the_caller() {
+    let _guard = common::lock();
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let response = CpiResponse {
+        compute_units_consumed: 10,
+        logs: vec![],
+        return_data: None,
+        accounts: vec![CpiAccount::new(&key, &owner, 500, &[1, 2, 3, 4])],
+        error: None,
+    };
+    let writable = account_manager::create_account_info(&key, false, true, 100, vec![0; 2], owner, false);
+    assert_eq!(cpi::apply_cpi_response_accounts(&response, std::slice::from_ref(&writable)), Ok(()));
+    assert_eq!(writable.lamports(), 500);
+    assert_eq!(&writable.data.borrow()[..], &[1, 2, 3, 4]);
+
+    // but not a readonly one
+    let readonly = account_manager::create_account_info(&key, false, false, 100, vec![0; 2], owner, false);
+    let result = cpi::apply_cpi_response_accounts(&response, std::slice::from_ref(&readonly));
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    assert_eq!(readonly.lamports(), 100);
+    account_manager::clear();
 }
@@ -0,0 +1,188 @@
+mod common;
+
+use std::{str::FromStr, sync::MutexGuard};
+
+use cartesi_solana::{
+    adapter::eth_address_to_pubkey,
+    eth_signature::{recover_signers, EthSignature, EthSignatureScheme},
+    executor::Executor,
+    transaction::{Signature, Transaction},
+};
+use common::MyLineReader;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    keccak,
+    message::Message,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+const DAPP_ADDRESS: [u8; 20] = [0x42; 20];
+const CHAIN_ID: u64 = 31337;
+
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup_data_path();
+    std::env::set_var("DAPP_ADDRESS", format!("0x{}", hex::encode(DAPP_ADDRESS)));
+    std::env::set_var("CHAIN_ID", CHAIN_ID.to_string());
+    guard
+}
+
+#[test]
+fn it_should_recover_personal_sign_and_typed_data_signers() {
+    let message = b"solana message".to_vec();
+    let alice = libsecp256k1::SecretKey::parse(&[1; 32]).unwrap();
+    let bob = libsecp256k1::SecretKey::parse(&[2; 32]).unwrap();
+    let signatures = vec![
+        sign(&alice, EthSignatureScheme::PersonalSign, &message, CHAIN_ID),
+        sign(&bob, EthSignatureScheme::TypedData, &message, CHAIN_ID),
+    ];
+    let signers = recover_signers(&signatures, &message, &DAPP_ADDRESS, CHAIN_ID).unwrap();
+    assert_eq!(signers, vec![pubkey_of(&alice), pubkey_of(&bob)]);
+}
+
+#[test]
+fn it_should_not_recover_the_signer_on_another_chain() {
+    let message = b"solana message".to_vec();
+    let alice = libsecp256k1::SecretKey::parse(&[1; 32]).unwrap();
+    let signatures = vec![sign(&alice, EthSignatureScheme::TypedData, &message, 1)];
+    let signers = recover_signers(&signatures, &message, &DAPP_ADDRESS, CHAIN_ID).unwrap();
+    assert_ne!(signers, vec![pubkey_of(&alice)]);
+}
+
+#[test]
+fn executor_should_mark_every_ethereum_signer_of_a_relayed_input() {
+    let _guard = setup();
+    let alice = libsecp256k1::SecretKey::parse(&[1; 32]).unwrap();
+    let bob = libsecp256k1::SecretKey::parse(&[2; 32]).unwrap();
+    let (payload, eth_signatures) = create_relayed_payload(&alice, &bob, Pubkey::new_unique());
+
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        assert!(accounts[0].is_signer);
+        assert!(accounts[1].is_signer);
+        assert!(!accounts[2].is_signer);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_not_run_a_relayed_input_twice() {
+    let _guard = setup();
+    let alice = libsecp256k1::SecretKey::parse(&[1; 32]).unwrap();
+    let bob = libsecp256k1::SecretKey::parse(&[2; 32]).unwrap();
+    let (payload, eth_signatures) = create_relayed_payload(&alice, &bob, Pubkey::new_unique());
+
+    // failed transactions aren't persisted, so they can be sent again
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Err(ProgramError::Custom(1)));
+    assert_eq!(result, Err(ProgramError::Custom(1)));
+
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+    assert_eq!(result, Ok(()));
+
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        panic!("a replayed input should not reach the program");
+    });
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+
+    // nor with a subset of the signatures
+    let eth_signatures: Vec<EthSignature> =
+        bincode::deserialize(&base64::decode(&eth_signatures).unwrap()).unwrap();
+    let eth_signatures = base64::encode(bincode::serialize(&eth_signatures[..1]).unwrap());
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        panic!("a replayed input should not reach the program");
+    });
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+
+    // a new message signed by the same users runs
+    let (payload, eth_signatures) = create_relayed_payload(&alice, &bob, Pubkey::new_unique());
+    let mut executor = Executor::create_with_stdin(create_relayed_stdin(&payload, &eth_signatures));
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+    assert_eq!(result, Ok(()));
+}
+
+//
+// Helper functions
+//
+
+fn eth_address_of(secret_key: &libsecp256k1::SecretKey) -> [u8; 20] {
+    let public_key = libsecp256k1::PublicKey::from_secret_key(secret_key).serialize();
+    let hash = keccak::hash(&public_key[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash.as_ref()[12..]);
+    address
+}
+
+fn pubkey_of(secret_key: &libsecp256k1::SecretKey) -> Pubkey {
+    eth_address_to_pubkey(&eth_address_of(secret_key))
+}
+
+fn sign(
+    secret_key: &libsecp256k1::SecretKey,
+    scheme: EthSignatureScheme,
+    message: &[u8],
+    chain_id: u64,
+) -> EthSignature {
+    let unsigned = EthSignature {
+        scheme,
+        signature: vec![],
+    };
+    let digest = unsigned.digest(message, &DAPP_ADDRESS, chain_id);
+    let (signature, recovery_id) =
+        libsecp256k1::sign(&libsecp256k1::Message::parse(&digest), secret_key);
+    let mut bytes = signature.serialize().to_vec();
+    bytes.push(recovery_id.serialize() + 27);
+    EthSignature {
+        scheme,
+        signature: bytes,
+    }
+}
+
+/// A transaction signed by `alice` and `bob`, with `account` as the third one.
+fn create_relayed_payload(
+    alice: &libsecp256k1::SecretKey,
+    bob: &libsecp256k1::SecretKey,
+    account: Pubkey,
+) -> (String, String) {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let instruction = Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(pubkey_of(alice), true),
+            AccountMeta::new(pubkey_of(bob), true),
+            AccountMeta::new(account, false),
+        ],
+        data: vec![1],
+    };
+    let message = Message::new(&[instruction], Some(&pubkey_of(alice)));
+    let message_bytes = message.serialize();
+    let eth_signatures = vec![
+        sign(alice, EthSignatureScheme::PersonalSign, &message_bytes, CHAIN_ID),
+        sign(bob, EthSignatureScheme::TypedData, &message_bytes, CHAIN_ID),
+    ];
+    let signatures = (0..message.header.num_required_signatures)
+        .map(|_| bincode::deserialize::<Signature>(&[0; 64]).unwrap())
+        .collect();
+    let transaction = Transaction {
+        signatures,
+        message,
+    };
+    let payload = base64::encode(bincode::serialize(&transaction).unwrap());
+    let eth_signatures = base64::encode(bincode::serialize(&eth_signatures).unwrap());
+    (payload, eth_signatures)
+}
+
+fn create_relayed_stdin(payload: &str, eth_signatures: &str) -> MyLineReader {
+    MyLineReader::create(vec![
+        "Header: Relayed CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266", // relayer
+        payload,
+        eth_signatures,
+        "0",     // instruction index
+        "12345", // timestamp
+    ])
+}
@@ -1,51 +1,45 @@
+mod common;
+
 use borsh::BorshSerialize;
 use cartesi_solana::{
     account_manager::{self, create_account_manager, AccountFileData},
     adapter::{self, load_account_info_data},
-    cartesi_stub::AccountInfoSerialize,
-    executor::{DefaultStdin, Executor, LineReader},
+    cartesi_stub::{self, AccountInfoSerialize},
+    cpi,
+    executor::{DefaultStdin, Executor},
     owner_manager,
     transaction::{self, Signature},
 };
-use solana_program::{account_info::AccountInfo, instruction::AccountMeta, pubkey::Pubkey};
+use solana_program::{
+    account_info::AccountInfo, instruction::AccountMeta, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+#[allow(deprecated)]
+use solana_program::sysvar::{
+    self, clock::Clock, epoch_rewards::EpochRewards, epoch_schedule::EpochSchedule, fees::Fees, instructions,
+    rent::Rent, slot_hashes::SlotHashes, Sysvar,
+};
 use solana_program::{
     hash::Hash,
     instruction::CompiledInstruction,
-    message::{Message, MessageHeader},
-};
-use std::{
-    fmt::Write,
-    fs, io,
-    str::FromStr,
-    time::{SystemTime, UNIX_EPOCH},
+    message::{v0, Message, MessageHeader, VersionedMessage},
 };
+use solana_sdk::signature::{Keypair, Signer};
+use common::MyLineReader;
+use std::{str::FromStr, sync::MutexGuard};
 
-fn setup() {
-    println!("\n\n***** setup *****\n");
-    let dir = std::env::temp_dir();
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    let final_temp_dir = format!(
-        "{}/{}",
-        dir.as_os_str().to_str().unwrap(),
-        since_the_epoch.subsec_nanos()
-    );
-    println!("{}", final_temp_dir);
-    fs::create_dir(&final_temp_dir).unwrap();
-    std::env::set_var("SOLANA_DATA_PATH", final_temp_dir);
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup();
     std::env::set_var(
         "PORTAL_ADDRESS",
         "0xf8c694fd58360de278d5ff2276b7130bfdc0192a",
     );
-    owner_manager::clear();
-    account_manager::clear();
+    guard
 }
 
 #[test]
 fn executor_should_load_program_args() {
-    setup();
+    let _guard = setup();
     let payload = create_payload();
     let stdin = MyLineReader::create(vec![
         "Header: External CPI",
@@ -59,7 +53,7 @@ fn executor_should_load_program_args() {
 
     create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
 
-    executor.get_processor_args(|program_id, accounts, data| {
+    let result = executor.get_processor_args(|program_id, accounts, data| {
         assert_eq!(
             program_id.to_string(),
             "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv".to_string()
@@ -67,12 +61,272 @@ fn executor_should_load_program_args() {
         assert_eq!(accounts.len(), 6);
         assert_eq!(data, &[141, 132, 233, 130, 168, 183, 10, 119]);
         assert_eq!(adapter::get_timestamp(), 12345);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_derive_the_clock_from_the_input_metadata() {
+    let _guard = setup();
+    let payload = create_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",               // instruction index
+        "12345 100 41 2", // timestamp, block number, input index and epoch index
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
+
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        let clock = Clock::get().unwrap();
+        assert_eq!(clock.unix_timestamp, 12345);
+        assert_eq!(clock.slot, 42);
+        assert_eq!(clock.epoch, 0);
+        let epoch_schedule = EpochSchedule::get().unwrap();
+        assert_eq!(epoch_schedule.get_epoch(clock.slot), clock.epoch);
+        assert_eq!(epoch_schedule.get_leader_schedule_epoch(clock.slot), clock.leader_schedule_epoch);
+        #[allow(deprecated)]
+        let fees = Fees::get().unwrap();
+        assert_eq!(fees.fee_calculator.lamports_per_signature, 0);
+        assert_eq!(EpochRewards::get().unwrap().total_rewards, 0);
+        assert_eq!(adapter::get_input_metadata().unwrap().block_number, 100);
+        assert_eq!(adapter::get_timestamp_line(), "12345 100 41 2");
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    adapter::set_timestamp_line("12345").unwrap();
+    assert_eq!(adapter::get_input_metadata(), None);
+    assert_eq!(cartesi_stub::current_clock().slot, 1);
+}
+
+#[test]
+fn executor_should_reject_input_metadata_out_of_the_range_of_the_slots() {
+    let _guard = setup();
+    let payload = create_payload();
+    let max_input_index = u64::MAX.to_string();
+    let timestamp_line = format!("12345 100 {} 0", max_input_index);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0", // instruction index
+        &timestamp_line,
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        panic!("the input should be rejected before running the program");
+    });
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+
+    // the epoch index and the input index plus 1 take 32 bits each
+    std::env::set_var("SOLANA_CLOCK_SLOT", "epoch_and_input_index");
+    let rejected = [
+        adapter::set_timestamp_line("12345 100 4294967295 0"),
+        adapter::set_timestamp_line("12345 100 0 4294967296"),
+    ];
+    let accepted = adapter::set_timestamp_line("12345 100 4294967294 4294967295");
+    let slot = cartesi_stub::current_clock().slot;
+    std::env::remove_var("SOLANA_CLOCK_SLOT");
+    assert_eq!(rejected, [Err(ProgramError::InvalidArgument), Err(ProgramError::InvalidArgument)]);
+    assert_eq!(accepted, Ok(()));
+    assert_eq!(slot, u64::MAX);
+}
+
+#[test]
+fn executor_should_synthesize_the_sysvar_accounts() {
+    let _guard = setup();
+    let payload = create_sysvar_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",              // instruction index
+        "12345 100 9 0", // timestamp, block number, input index and epoch index
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        assert_eq!(accounts.len(), 3);
+        assert!(accounts.iter().all(|account| account.owner == &sysvar::id()));
+        let clock = Clock::from_account_info(&accounts[0]).unwrap();
+        assert_eq!(clock, Clock::get().unwrap());
+        assert_eq!(clock.slot, 10);
+        let rent = Rent::from_account_info(&accounts[1]).unwrap();
+        assert_eq!(rent, Rent::get().unwrap());
+        // too big for from_account_info, programs read the data instead
+        let slot_hashes: SlotHashes = bincode::deserialize(&accounts[2].data.borrow()).unwrap();
+        assert_eq!(slot_hashes.len(), 10);
+        assert!(slot_hashes.get(&9).is_some());
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+    let (_, _, owner) = load_account_info_data(&sysvar::clock::id());
+    assert_eq!(owner, sysvar::id());
+    assert!(create_account_manager().read_account(&sysvar::clock::id()).is_err());
+}
+
+#[test]
+fn executor_should_load_program_args_from_v0_transaction() {
+    let _guard = setup();
+    let payload = create_v0_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
+
+    let result = executor.get_processor_args(|program_id, accounts, data| {
+        assert_eq!(
+            program_id.to_string(),
+            "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv".to_string()
+        );
+        assert_eq!(accounts.len(), 6);
+        assert_eq!(
+            accounts[0].key.to_string(),
+            "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY"
+        );
+        assert_eq!(accounts[0].data_len(), 32);
+        assert!(accounts[1].is_signer);
+        assert_eq!(data, &[141, 132, 233, 130, 168, 183, 10, 119]);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_mark_ed25519_signers_when_verifying_signatures() {
+    let _guard = setup();
+    let keypair = Keypair::new();
+    let payload = create_signed_payload(&keypair, false);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+    executor.verify_signatures = true;
+    let signer = keypair.pubkey();
+    let result = executor.get_processor_args(move |_program_id, accounts, _data| {
+        assert_eq!(accounts[0].key, &signer);
+        assert!(accounts[0].is_signer);
+        assert!(!accounts[1].is_signer);
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_pass_an_ed25519_signer_through_cpi() {
+    let _guard = setup();
+    let keypair = Keypair::new();
+    let account_file_data = AccountFileData {
+        owner: solana_program::system_program::id(),
+        data: vec![],
+        lamports: 1000,
+    };
+    create_account_manager().write_account(&keypair.pubkey(), &account_file_data).unwrap();
+    let payload = create_signed_payload(&keypair, false);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+    executor.verify_signatures = true;
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let instruction = solana_program::system_instruction::transfer(accounts[0].key, accounts[1].key, 10);
+        solana_program::program::invoke(&instruction, &accounts[..])
+    });
+    assert_eq!(result, Ok(()));
+    let (_, lamports, _) = load_account_info_data(&keypair.pubkey());
+    assert_eq!(lamports, 990);
+}
+
+#[test]
+fn executor_should_not_trust_ed25519_signatures_by_default() {
+    let _guard = setup();
+    let keypair = Keypair::new();
+    let payload = create_signed_payload(&keypair, false);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        assert!(!accounts[0].is_signer);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_reject_a_tampered_signature() {
+    let _guard = setup();
+    let keypair = Keypair::new();
+    let payload = create_signed_payload(&keypair, true);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+    executor.verify_signatures = true;
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        panic!("should not run the program");
+    });
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn executor_should_not_run_an_ed25519_signed_transaction_twice() {
+    let _guard = setup();
+    let keypair = Keypair::new();
+    let payload = create_signed_payload(&keypair, false);
+    for expected in [Ok(()), Err(ProgramError::InvalidArgument)] {
+        // anyone can send the input again, under their own msg_sender
+        let stdin = MyLineReader::create(vec![
+            "Header: External CPI",
+            "0x70997970C51812dc3A010C7d01b50e0d17dc79C8",
+            &payload,
+            "0",     // instruction index
+            "12345", // timestamp
+        ]);
+        let mut executor = Executor::create_with_stdin(stdin);
+        executor.verify_signatures = true;
+        let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+        assert_eq!(result, expected);
+    }
 }
 
 #[test]
 fn executor_should_call_crazy_lifetime() {
-    setup();
+    let _guard = setup();
     let payload = create_payload();
     let stdin = MyLineReader::create(vec![
         "Header: External CPI",
@@ -86,9 +340,11 @@ fn executor_should_call_crazy_lifetime() {
 
     create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
 
-    executor.get_processor_args(|program_id, accounts, data| {
+    let result = executor.get_processor_args(|program_id, accounts, data| {
         process_instruction(&program_id, accounts, &data);
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
 }
 fn process_instruction<'a>(
     _program_id: &'a Pubkey,
@@ -99,7 +355,7 @@ fn process_instruction<'a>(
 
 #[test]
 fn executor_should_load_change_and_save_account_infos() {
-    setup();
+    let _guard = setup();
     let payload = create_payload();
     let stdin = MyLineReader::create(vec![
         "Header: External CPI",
@@ -113,7 +369,7 @@ fn executor_should_load_change_and_save_account_infos() {
 
     create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
 
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let borsh_structure = BorshStructure {
             key: Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap(),
         };
@@ -124,7 +380,9 @@ fn executor_should_load_change_and_save_account_infos() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
 
     let (data, _, _) = load_account_info_data(
         &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
@@ -135,7 +393,7 @@ fn executor_should_load_change_and_save_account_infos() {
 
 #[test]
 fn executor_should_change_the_owner() {
-    setup();
+    let _guard = setup();
     let payload = create_payload();
     let stdin = MyLineReader::create(vec![
         "Header: External CPI",
@@ -147,7 +405,7 @@ fn executor_should_change_the_owner() {
 
     let mut executor = Executor::create_with_stdin(stdin);
 
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let borsh_structure = BorshStructure {
             key: Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap(),
         };
@@ -159,7 +417,9 @@ fn executor_should_change_the_owner() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
 
     let (_, _, owner) = load_account_info_data(
         &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
@@ -168,9 +428,153 @@ fn executor_should_change_the_owner() {
     assert_eq!(owner, expected);
 }
 
+#[test]
+fn executor_should_honor_the_message_header_writable_flags() {
+    let _guard = setup();
+    let payload = create_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let writable: Vec<bool> = accounts.iter().map(|account| account.is_writable).collect();
+        assert_eq!(writable, vec![true, true, false, false, false, false]);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_should_reject_a_modified_readonly_account() {
+    let _guard = setup();
+    let payload = create_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "0",     // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        **accounts[0].lamports.try_borrow_mut().unwrap() += 100;
+        let readonly_account = &accounts[2];
+        assert!(!readonly_account.is_writable);
+        **readonly_account.lamports.try_borrow_mut().unwrap() += 100;
+        Ok(())
+    });
+    assert_eq!(result, Err(ProgramError::InvalidAccountData));
+
+    let (_, lamports, _) = load_account_info_data(
+        &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+    );
+    assert_eq!(lamports, 0);
+}
+
+#[test]
+fn executor_should_run_all_instructions_over_the_same_accounts() {
+    let _guard = setup();
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 0);
+    let payload = create_two_instructions_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",   // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|_program_id, accounts, data| {
+        let account_info = &accounts[0];
+        let expected_lamports = 100 + 10 * (data[0] as u64 - 1);
+        assert_eq!(account_info.lamports(), expected_lamports);
+        **account_info.lamports.try_borrow_mut().unwrap() += 10;
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let (_, lamports, _) = load_account_info_data(
+        &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+    );
+    assert_eq!(lamports, 120);
+}
+
+#[test]
+fn executor_should_expose_the_instructions_sysvar() {
+    let _guard = setup();
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 0);
+    let payload = create_introspection_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",   // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|program_id, accounts, data| {
+        let instructions_info = &accounts[1];
+        let current_index = instructions::load_current_index_checked(instructions_info).unwrap();
+        assert_eq!(current_index, data[0] as u16 - 1);
+        let first = instructions::load_instruction_at_checked(0, instructions_info).unwrap();
+        assert_eq!(&first.program_id, program_id);
+        assert_eq!(first.data, vec![1]);
+        assert_eq!(first.accounts[0].pubkey, *accounts[0].key);
+        assert!(first.accounts[0].is_writable);
+        assert!(!first.accounts[1].is_writable);
+        let current = instructions::get_instruction_relative(0, instructions_info).unwrap();
+        assert_eq!(&current.data, data);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+    assert!(create_account_manager().read_account(&instructions::id()).is_err());
+}
+
+#[test]
+fn executor_should_not_persist_anything_when_an_instruction_fails() {
+    let _guard = setup();
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 0);
+    let payload = create_two_instructions_payload();
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",   // instruction index
+        "12345", // timestamp
+    ]);
+
+    let mut executor = Executor::create_with_stdin(stdin);
+
+    let result = executor.get_processor_args(|_program_id, accounts, data| {
+        **accounts[0].lamports.try_borrow_mut().unwrap() += 10;
+        match data[0] {
+            1 => Ok(()),
+            _ => Err(ProgramError::Custom(42)),
+        }
+    });
+    assert_eq!(result, Err(ProgramError::Custom(42)));
+
+    let (_, lamports, _) = load_account_info_data(
+        &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+    );
+    assert_eq!(lamports, 100);
+}
+
 #[test]
 fn executor_should_save_account_info_resized() {
-    setup();
+    let _guard = setup();
     let payload = create_payload();
     let stdin = MyLineReader::create(vec![
         "Header: External CPI",
@@ -182,7 +586,7 @@ fn executor_should_save_account_info_resized() {
 
     let mut executor = Executor::create_with_stdin(stdin);
 
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let borsh_structure = BorshStructure {
             key: Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap(),
         };
@@ -192,7 +596,9 @@ fn executor_should_save_account_info_resized() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
 
     let (data, _, _) = load_account_info_data(
         &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
@@ -209,7 +615,7 @@ fn executor_with_default_stdin() {
 
 #[test]
 fn executor_cpi_read_arguments() {
-    setup();
+    let _guard = setup();
     let payload = create_instruction_payload();
     let cpi_accounts = create_cpi_accounts(0);
     let signers_seeds = create_signers_seeds();
@@ -223,18 +629,57 @@ fn executor_cpi_read_arguments() {
         &caller_program_id,
     ]);
     let mut executor = Executor::create_with_stdin(stdin);
-    executor.get_processor_args(|program_id, accounts, data| {
+    let result = executor.get_processor_args(|program_id, accounts, data| {
         let spl_token_program_id =
             Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
         assert_eq!(program_id, &spl_token_program_id);
         assert_eq!(accounts.len(), 1);
         assert_eq!(data, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 0]);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn executor_cpi_should_not_escalate_the_writable_privilege() {
+    let _guard = setup();
+    create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 0);
+    let payload = create_instruction_payload();
+    // readonly for the caller, writable in the instruction
+    let cpi_accounts: Vec<AccountInfoSerialize> = vec![AccountInfoSerialize {
+        key: Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+        is_signer: false,
+        is_writable: false,
+        lamports: 0,
+        data: vec![],
+        owner: Pubkey::default(),
+        executable: false,
+        rent_epoch: 1,
+    }];
+    let cpi_accounts = base64::encode(bincode::serialize(&cpi_accounts).unwrap());
+    let signers_seeds = create_signers_seeds();
+    let caller_program_id = create_cpi_program_id();
+    let stdin = MyLineReader::create(vec![
+        "Header: CPI",
+        &payload,
+        &cpi_accounts,
+        &signers_seeds,
+        "12345", // timestamp
+        &caller_program_id,
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        **accounts[0].try_borrow_mut_lamports()? = 1_000_000;
+        Ok(())
     });
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let (_, lamports, _) = load_account_info_data(&Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap());
+    assert_eq!(lamports, 100);
 }
 
 #[test]
-fn executor_cpi_save_borsh_serialization() {
-    setup();
+fn executor_cpi_send_back_borsh_serialization() {
+    let _guard = setup();
     let payload = create_instruction_payload();
     let cpi_accounts = create_cpi_accounts(32);
     let signers_seeds = create_signers_seeds();
@@ -249,7 +694,7 @@ fn executor_cpi_save_borsh_serialization() {
     ]);
 
     let mut executor = Executor::create_with_stdin(stdin);
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let borsh_structure = BorshStructure {
             key: Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap(),
         };
@@ -258,17 +703,18 @@ fn executor_cpi_save_borsh_serialization() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
-    let (data, _, _) = load_account_info_data(
-        &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
-    );
+    assert_eq!(result, Ok(()));
+    let account = cpi_response_account("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY");
     let expected = Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap();
-    assert_eq!(data, expected.to_bytes());
+    assert_eq!(account.data, expected.to_bytes());
+    assert_eq!(account.lamports, 100);
 }
 
 #[test]
-fn executor_cpi_save_borsh_serialization_with_account_data_resize() {
-    setup();
+fn executor_cpi_send_back_borsh_serialization_with_account_data_resize() {
+    let _guard = setup();
     let payload = create_instruction_payload();
     let cpi_accounts = create_cpi_accounts(0);
     let signers_seeds = create_signers_seeds();
@@ -283,7 +729,7 @@ fn executor_cpi_save_borsh_serialization_with_account_data_resize() {
     ]);
 
     let mut executor = Executor::create_with_stdin(stdin);
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let borsh_structure = BorshStructure {
             key: Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap(),
         };
@@ -295,17 +741,18 @@ fn executor_cpi_save_borsh_serialization_with_account_data_resize() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
-    let (data, _, _) = load_account_info_data(
-        &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
-    );
+    assert_eq!(result, Ok(()));
+    let account = cpi_response_account("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY");
     let expected = Pubkey::from_str("4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV").unwrap();
-    assert_eq!(data, expected.to_bytes());
+    assert_eq!(account.data, expected.to_bytes());
+    assert_eq!(account.lamports, 100);
 }
 
 #[test]
-fn executor_cpi_save_new_owner() {
-    setup();
+fn executor_cpi_send_back_new_owner() {
+    let _guard = setup();
     let payload = create_instruction_payload();
     let cpi_accounts = create_cpi_accounts(0);
     let signers_seeds = create_signers_seeds();
@@ -322,23 +769,30 @@ fn executor_cpi_save_new_owner() {
     create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
 
     let mut executor = Executor::create_with_stdin(stdin);
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let account_info = &accounts[0];
         let new_owner = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
         owner_manager::change_owner(*account_info.key, new_owner);
         **account_info.lamports.try_borrow_mut().unwrap() += 1234567;
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
+    let account = cpi_response_account("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY");
+    let expected = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+    assert_eq!(account.owner, expected);
+    assert_eq!(account.lamports, 1234567);
+
+    // the transaction of the caller persists it
     let (_, lamports, owner) = load_account_info_data(
         &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
     );
-    let expected = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
-    assert_eq!(owner, expected);
-    assert_eq!(lamports, 1234567);
+    assert_eq!(owner, Pubkey::default());
+    assert_eq!(lamports, 100);
 }
 
 #[test]
-fn executor_cpi_save_new_owner_and_serialize() {
-    setup();
+fn executor_cpi_send_back_new_owner_and_serialize() {
+    let _guard = setup();
     let payload = create_instruction_payload();
     let cpi_accounts = create_cpi_accounts(32);
     let signers_seeds = create_signers_seeds();
@@ -355,7 +809,7 @@ fn executor_cpi_save_new_owner_and_serialize() {
     create_account_with_space("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY", 32);
 
     let mut executor = Executor::create_with_stdin(stdin);
-    executor.get_processor_args(|_program_id, accounts, _data| {
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
         let account_info = &accounts[0];
         let expected_account_key =
             Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
@@ -373,19 +827,37 @@ fn executor_cpi_save_new_owner_and_serialize() {
         borsh_structure
             .serialize(&mut *account_info.try_borrow_mut_data().unwrap())
             .unwrap();
+        Ok(())
     });
+    assert_eq!(result, Ok(()));
+    let account = cpi_response_account("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY");
+    let expected = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+    assert_eq!(account.owner, expected);
+    assert_eq!(account.lamports, 1234567);
+
+    // the transaction of the caller persists it
     let (_, lamports, owner) = load_account_info_data(
         &Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
     );
-    let expected = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
-    assert_eq!(owner, expected);
-    assert_eq!(lamports, 1234567);
+    assert_eq!(owner, Pubkey::default());
+    assert_eq!(lamports, 100);
 }
 
 //
 // Helper functions
 //
 
+/// The account as the called program sends it back to its caller.
+fn cpi_response_account(key: &str) -> AccountFileData {
+    let key = Pubkey::from_str(key).unwrap();
+    cpi::get_response_accounts()
+        .iter()
+        .map(|account| account.decode())
+        .find(|(account_key, _)| account_key == &key)
+        .map(|(_, account)| account)
+        .expect("the account is not in the CPI response")
+}
+
 fn create_cpi_program_id() -> String {
     let key = Pubkey::from_str("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s").unwrap();
     let key = bincode::serialize(&key).unwrap();
@@ -410,37 +882,12 @@ fn create_account_with_space(key: &str, space: usize) {
 struct BorshStructure {
     key: Pubkey,
 }
-
-struct MyLineReader {
-    pub lines: Vec<String>,
-    pub current_line: usize,
-}
-
-impl MyLineReader {
-    fn create(lines: Vec<&str>) -> Self {
-        let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
-        Self {
-            lines,
-            current_line: 0,
-        }
-    }
-}
-
-impl LineReader for MyLineReader {
-    fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
-        let line_with_ender = format!("{}\n", &self.lines[self.current_line]);
-        buf.write_str(&line_with_ender).unwrap();
-        self.current_line += 1;
-        Ok(1)
-    }
-}
-
 fn create_instruction_payload() -> String {
     let program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
     let account_meta = AccountMeta {
         pubkey: Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
         is_signer: false,
-        is_writable: false,
+        is_writable: true,
     };
     let accounts = vec![account_meta];
     let data = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 0];
@@ -464,7 +911,7 @@ fn create_cpi_accounts(data_size: usize) -> String {
     let accounts: Vec<AccountInfoSerialize> = vec![AccountInfoSerialize {
         key: Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
         is_signer: false,
-        is_writable: false,
+        is_writable: true,
         lamports: 0,
         data: vec![0u8; data_size],
         owner: Pubkey::default(),
@@ -475,6 +922,138 @@ fn create_cpi_accounts(data_size: usize) -> String {
     base64::encode(serialized)
 }
 
+fn create_signed_payload(keypair: &Keypair, tamper: bool) -> String {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let instruction = solana_program::instruction::Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new(keypair.pubkey(), true),
+            AccountMeta::new(
+                Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+                false,
+            ),
+        ],
+        data: vec![1],
+    };
+    let message = Message::new(&[instruction], Some(&keypair.pubkey()));
+    let mut message_bytes = message.serialize();
+    if tamper {
+        message_bytes.push(0);
+    }
+    let signature = keypair.sign_message(&message_bytes);
+    let signature: Signature = bincode::deserialize(signature.as_ref()).unwrap();
+    let transaction = transaction::Transaction {
+        signatures: vec![signature],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
+fn create_v0_payload() -> String {
+    let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
+    let signatures = vec![signature];
+    let header = MessageHeader {
+        num_required_signatures: 1,
+        num_readonly_signed_accounts: 0,
+        num_readonly_unsigned_accounts: 5,
+    };
+    let account_keys = vec![
+        "1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2",
+        "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY",
+        "11111111111111111111111111111111",
+        "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv",
+        "4xRtyUw1QSVZSGi1BUb7nbYBk8TC9P1K1AE2xtxwaZmV",
+        "SysvarRent111111111111111111111111111111111",
+        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+    ]
+    .into_iter()
+    .map(|str_pk| Pubkey::from_str(str_pk).unwrap())
+    .collect();
+
+    let instruction = CompiledInstruction {
+        program_id_index: 3,
+        accounts: [1, 0, 4, 6, 2, 5].to_vec(),
+        data: vec![141, 132, 233, 130, 168, 183, 10, 119],
+    };
+
+    let message = v0::Message {
+        header,
+        account_keys,
+        recent_blockhash: Hash::default(),
+        instructions: vec![instruction],
+        address_table_lookups: vec![],
+    };
+    let transaction = transaction::VersionedTransaction {
+        signatures,
+        message: VersionedMessage::V0(message),
+    };
+    let transaction_bytes = bincode::serialize(&transaction).unwrap();
+    base64::encode(transaction_bytes)
+}
+
+fn create_sysvar_payload() -> String {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payer = Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap();
+    let instruction = solana_program::instruction::Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(sysvar::clock::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(sysvar::slot_hashes::id(), false),
+        ],
+        data: vec![],
+    };
+    let message = Message::new(&[instruction], Some(&payer));
+    let transaction = transaction::Transaction {
+        signatures: vec![bincode::deserialize(&[0; 64]).unwrap()],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
+fn create_introspection_payload() -> String {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payer = Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap();
+    let account = Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
+    let instructions: Vec<solana_program::instruction::Instruction> = [1, 2]
+        .into_iter()
+        .map(|step| solana_program::instruction::Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(account, false),
+                AccountMeta::new_readonly(instructions::id(), false),
+            ],
+            data: vec![step],
+        })
+        .collect();
+    let message = Message::new(&instructions, Some(&payer));
+    let transaction = transaction::Transaction {
+        signatures: vec![bincode::deserialize(&[0; 64]).unwrap()],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
+fn create_two_instructions_payload() -> String {
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payer = Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap();
+    let account = Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
+    let instructions: Vec<solana_program::instruction::Instruction> = [1, 2]
+        .into_iter()
+        .map(|step| solana_program::instruction::Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new(account, false)],
+            data: vec![step],
+        })
+        .collect();
+    let message = Message::new(&instructions, Some(&payer));
+    let transaction = transaction::Transaction {
+        signatures: vec![bincode::deserialize(&[0; 64]).unwrap()],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
 fn create_payload() -> String {
     let signature: Signature = bincode::deserialize(&[0; 64]).unwrap();
     let signatures = vec![signature];
@@ -1,3 +1,5 @@
+mod common;
+
 use borsh::BorshSerialize;
 use cartesi_solana::account_manager::{self, create_account_info, serialize_with_padding};
 use solana_program::account_info::AccountInfo;
@@ -41,6 +43,7 @@ fn it_should_serialize_to_account_info() {
 
 #[test]
 fn it_should_serialize_with_shared_data() {
+    let _guard = common::lock();
     let lamports = 1;
     let space = 42;
     let owner = Pubkey::default();
@@ -67,6 +70,7 @@ fn it_should_serialize_with_shared_data() {
 
 #[test]
 fn it_should_serialize_with_shared_data_2() {
+    let _guard = common::lock();
     let lamports = 1;
     let space = 42;
     let owner = Pubkey::default();
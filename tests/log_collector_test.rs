@@ -0,0 +1,228 @@
+mod common;
+
+use cartesi_solana::{
+    cartesi_stub::AccountInfoSerialize,
+    executor::Executor,
+    log_collector::{self, LOG_MESSAGES_BYTES_LIMIT},
+    owner_manager, transaction,
+};
+use common::{setup, MyLineReader};
+use solana_program::{
+    instruction::{get_stack_height, AccountMeta, Instruction},
+    log::{sol_log_64, sol_log_data},
+    message::Message,
+    msg,
+    program::{get_return_data, set_return_data},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::str::FromStr;
+
+#[test]
+fn executor_should_collect_the_program_logs() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payload = create_payload(&program_id);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|program_id, _accounts, data| {
+        assert_eq!(get_stack_height(), 1);
+        msg!("step {}", data[0]);
+        if data[0] == 2 {
+            sol_log_data(&[b"hello", b"world"]);
+            sol_log_64(1, 2, 3, 4, 5);
+            program_id.log();
+        }
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let logs = log_collector::get_logs();
+    assert_eq!(logs.len(), 11);
+    assert_eq!(logs[0], format!("Program {} invoke [1]", program_id));
+    assert_eq!(logs[1], "Program log: step 1");
+    assert!(logs[2].starts_with(&format!("Program {} consumed ", program_id)));
+    assert!(logs[2].ends_with(" compute units"));
+    assert_eq!(logs[3], format!("Program {} success", program_id));
+    assert_eq!(logs[5], "Program log: step 2");
+    assert_eq!(logs[6], "Program data: aGVsbG8= d29ybGQ=");
+    assert_eq!(logs[7], "Program log: 0x1, 0x2, 0x3, 0x4, 0x5");
+    assert_eq!(logs[8], format!("Program log: {}", program_id));
+    assert_eq!(logs[10], format!("Program {} success", program_id));
+}
+
+#[test]
+fn executor_should_log_the_failed_instruction() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payload = create_payload(&program_id);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Err(ProgramError::Custom(1)));
+    assert_eq!(result, Err(ProgramError::Custom(1)));
+
+    // the second instruction never runs
+    let logs = log_collector::get_logs();
+    assert_eq!(logs.len(), 3);
+    assert_eq!(logs[2], format!("Program {} failed: Custom program error: 0x1", program_id));
+}
+
+#[test]
+fn executor_should_not_log_the_owner_changes() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payload = create_payload(&program_id);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|program_id, accounts, _data| {
+        owner_manager::change_owner(*accounts[0].key, *program_id);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    // only the framing of the two instructions
+    let logs = log_collector::get_logs();
+    assert_eq!(logs.len(), 6);
+    assert!(logs.iter().all(|log| !log.contains("change_owner")));
+}
+
+#[test]
+fn executor_should_log_a_cpi_at_its_stack_height() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &[1],
+        vec![AccountMeta::new(
+            Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+            false,
+        )],
+    );
+    let instruction = base64::encode(bincode::serialize(&instruction).unwrap());
+    let accounts = create_cpi_accounts();
+    let signers_seeds: &[&[&[u8]]] = &[];
+    let signers_seeds = base64::encode(bincode::serialize(&signers_seeds).unwrap());
+    let caller_program_id = Pubkey::from_str("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s").unwrap();
+    let caller_program_id = base64::encode(bincode::serialize(&caller_program_id).unwrap());
+    let stdin = MyLineReader::create(vec![
+        "Header: CPI",
+        &instruction,
+        &accounts,
+        &signers_seeds,
+        "12345",
+        &caller_program_id,
+        "5000", // compute units left
+        "3",    // stack height
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| {
+        assert_eq!(get_stack_height(), 3);
+        msg!("called");
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let logs = log_collector::get_logs();
+    assert_eq!(logs[0], format!("Program {} invoke [3]", program_id));
+    assert_eq!(logs[1], "Program log: called");
+    assert!(logs[2].ends_with(" of 5000 compute units"));
+    assert_eq!(logs[3], format!("Program {} success", program_id));
+}
+
+#[test]
+fn executor_should_reset_the_return_data_before_each_instruction() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let payload = create_payload(&program_id);
+    let stdin = MyLineReader::create(vec![
+        "Header: External CPI",
+        "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266",
+        &payload,
+        "all",
+        "12345",
+    ]);
+    let mut executor = Executor::create_with_stdin(stdin);
+    let result = executor.get_processor_args(|program_id, _accounts, data| {
+        assert_eq!(get_return_data(), None);
+        if data[0] == 1 {
+            set_return_data(b"result");
+            assert_eq!(get_return_data(), Some((*program_id, b"result".to_vec())));
+        }
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let logs = log_collector::get_logs();
+    assert_eq!(logs[1], format!("Program return: {} cmVzdWx0", program_id));
+    assert_eq!(logs.iter().filter(|log| log.starts_with("Program return: ")).count(), 1);
+}
+
+#[test]
+fn it_should_truncate_the_logs() {
+    let _guard = setup();
+    log_collector::reset_logs();
+    let message = "x".repeat(100);
+    for _ in 0..200 {
+        log_collector::program_log(&message);
+    }
+    let logs = log_collector::get_logs();
+    assert_eq!(logs.last().unwrap(), "Log truncated");
+    assert_eq!(logs.iter().filter(|log| *log == "Log truncated").count(), 1);
+    let bytes: usize = logs[..logs.len() - 1].iter().map(|log| log.len()).sum();
+    assert!(bytes <= LOG_MESSAGES_BYTES_LIMIT);
+    assert!(bytes + message.len() > LOG_MESSAGES_BYTES_LIMIT - "Program log: ".len());
+
+    log_collector::reset_logs();
+    assert!(log_collector::get_logs().is_empty());
+}
+
+//
+// Helper functions
+//
+
+fn create_payload(program_id: &Pubkey) -> String {
+    let payer = Pubkey::from_str("1111111111112RXi1yn6kTp7G8Td7o6z3Ciqw9v2").unwrap();
+    let account = Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap();
+    let instructions: Vec<Instruction> = [1, 2]
+        .into_iter()
+        .map(|step| Instruction::new_with_bytes(*program_id, &[step], vec![AccountMeta::new(account, false)]))
+        .collect();
+    let message = Message::new(&instructions, Some(&payer));
+    let transaction = transaction::Transaction {
+        signatures: vec![bincode::deserialize(&[0; 64]).unwrap()],
+        message,
+    };
+    base64::encode(bincode::serialize(&transaction).unwrap())
+}
+
+fn create_cpi_accounts() -> String {
+    let accounts: Vec<AccountInfoSerialize> = vec![AccountInfoSerialize {
+        key: Pubkey::from_str("6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY").unwrap(),
+        is_signer: false,
+        is_writable: true,
+        lamports: 0,
+        data: vec![],
+        owner: Pubkey::default(),
+        executable: false,
+        rent_epoch: 1,
+    }];
+    base64::encode(bincode::serialize(&accounts).unwrap())
+}
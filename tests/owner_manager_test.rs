@@ -1,3 +1,5 @@
+mod common;
+
 use std::{cell::RefCell, rc::Rc, str::FromStr};
 
 use cartesi_solana::owner_manager;
@@ -13,6 +15,7 @@ fn entry2(accounts: &[&Box<AccountInfo>]) {
 
 #[test]
 fn it_should_change_the_account_owner() {
+    let _guard = common::lock();
     let owner: Pubkey = Pubkey::default();
     let key: &Pubkey = &Pubkey::default();
     let mut lamports: u64 = 1000;
@@ -0,0 +1,117 @@
+mod common;
+
+use cartesi_solana::{
+    adapter::{eth_address_to_pubkey, load_account_info_data},
+    cpi,
+    executor::Executor,
+    log_collector, owner_manager,
+};
+use common::{create_account, MyLineReader};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{get_stack_height, AccountMeta, Instruction},
+    msg,
+    program::{get_return_data, invoke, set_return_data},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::{str::FromStr, sync::MutexGuard};
+
+const CALLER: &str = "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv";
+const CALLEE: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+const ACCOUNT: &str = "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY";
+
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup();
+    cpi::register_program(Pubkey::from_str(CALLEE).unwrap(), callee_entrypoint);
+    create_account(&Pubkey::from_str(ACCOUNT).unwrap(), 1_000_000_000, Pubkey::default(), vec![0; 8]);
+    guard
+}
+
+#[test]
+fn executor_should_run_a_registered_program_in_process() {
+    let _guard = setup();
+    let mut executor = Executor::create_with_stdin(create_stdin(AccountMeta::new(Pubkey::from_str(ACCOUNT).unwrap(), false)));
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let callee = Pubkey::from_str(CALLEE).unwrap();
+        let instruction = Instruction::new_with_bytes(callee, &[], vec![AccountMeta::new(*accounts[0].key, false)]);
+        invoke(&instruction, &accounts[..])?;
+
+        // the same AccountInfo, changed by the called program
+        assert_eq!(accounts[0].lamports(), 1_000_000_010);
+        assert_eq!(accounts[0].data.borrow()[0], 1);
+        assert_eq!(accounts[0].owner, &callee);
+        assert_eq!(get_return_data(), Some((callee, b"done".to_vec())));
+        assert_eq!(get_stack_height(), 1);
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let (data, lamports, owner) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(lamports, 1_000_000_010);
+    assert_eq!(data[0], 1);
+    assert_eq!(owner, Pubkey::from_str(CALLEE).unwrap());
+
+    let logs = log_collector::get_logs();
+    assert_eq!(logs[1], format!("Program {} invoke [2]", CALLEE));
+    assert_eq!(logs[2], "Program log: called in process");
+    let position = |line: String| logs.iter().position(|log| log == &line).unwrap();
+    let callee_return = position(format!("Program return: {} ZG9uZQ==", CALLEE));
+    let callee_success = position(format!("Program {} success", CALLEE));
+    let caller_success = position(format!("Program {} success", CALLER));
+    assert!(callee_return < callee_success && callee_success < caller_success);
+}
+
+#[test]
+fn executor_should_not_escalate_the_privileges_of_a_registered_program() {
+    let _guard = setup();
+    let mut executor = Executor::create_with_stdin(create_stdin(AccountMeta::new_readonly(Pubkey::from_str(ACCOUNT).unwrap(), false)));
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let callee = Pubkey::from_str(CALLEE).unwrap();
+        let instruction = Instruction::new_with_bytes(callee, &[], vec![AccountMeta::new(*accounts[0].key, false)]);
+        invoke(&instruction, &accounts[..])
+    });
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let (_, lamports, _) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(lamports, 1_000_000_000);
+}
+
+#[test]
+fn executor_should_not_escalate_the_signer_privilege_for_a_registered_program() {
+    let _guard = setup();
+    // an Ethereum user, who doesn't sign this transaction
+    let victim = eth_address_to_pubkey(&hex::decode("70997970C51812dc3A010C7d01b50e0d17dc79C8").unwrap());
+    create_account(&victim, 1_000_000, Pubkey::default(), vec![0; 8]);
+    let mut executor = Executor::create_with_stdin(create_stdin(AccountMeta::new(victim, false)));
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let callee = Pubkey::from_str(CALLEE).unwrap();
+        let instruction = Instruction::new_with_bytes(callee, &[], vec![AccountMeta::new(*accounts[0].key, true)]);
+        invoke(&instruction, &accounts[..])
+    });
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    let (_, lamports, owner) = load_account_info_data(&victim);
+    assert_eq!(lamports, 1_000_000);
+    assert_eq!(owner, Pubkey::default());
+    let logs = log_collector::get_logs();
+    assert!(logs.contains(&format!("{}'s signer privilege escalated", victim)));
+}
+
+//
+// Helper functions
+//
+
+fn callee_entrypoint(program_id: &Pubkey, accounts: &[AccountInfo], _data: &[u8]) -> ProgramResult {
+    assert_eq!(get_stack_height(), 2);
+    msg!("called in process");
+    **accounts[0].try_borrow_mut_lamports()? += 10;
+    accounts[0].try_borrow_mut_data()?[0] = 1;
+    owner_manager::change_owner(*accounts[0].key, *program_id);
+    set_return_data(b"done");
+    Ok(())
+}
+
+fn create_stdin(meta: AccountMeta) -> MyLineReader {
+    let program_id = Pubkey::from_str(CALLER).unwrap();
+    common::create_stdin(&[Instruction::new_with_bytes(program_id, &[], vec![meta])])
+}
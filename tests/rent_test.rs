@@ -0,0 +1,92 @@
+mod common;
+
+use std::{cell::RefCell, rc::Rc, sync::MutexGuard};
+
+use cartesi_solana::{
+    account_manager,
+    adapter::{check_rent_exemption, snapshot_rent_state},
+    cartesi_stub,
+};
+use solana_program::{account_info::AccountInfo, program_error::ProgramError, pubkey::Pubkey};
+
+// the tests of this file share the same process, so the same configuration
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::lock();
+    std::env::set_var("SOLANA_RENT_ENFORCE", "true");
+    std::env::set_var("SOLANA_RENT_LAMPORTS_PER_BYTE_YEAR", "10");
+    std::env::set_var("SOLANA_RENT_EXEMPTION_THRESHOLD", "1.0");
+    guard
+}
+
+#[test]
+fn it_should_read_the_rent_from_the_environment() {
+    let _guard = setup();
+    let rent = cartesi_stub::current_rent();
+    assert_eq!(rent.lamports_per_byte_year, 10);
+    assert_eq!(rent.exemption_threshold, 1.0);
+    assert_eq!(rent.burn_percent, 50);
+    assert_eq!(rent.minimum_balance(10), 1380);
+    assert!(cartesi_stub::rent_enforced());
+}
+
+#[test]
+fn it_should_reject_new_accounts_below_the_minimum_balance() {
+    let _guard = setup();
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports: u64 = 0;
+    let mut data: Vec<u8> = vec![0; 10];
+    let account_info = create_account_info(&key, &owner, &mut lamports, &mut data);
+    let accounts = vec![account_info];
+
+    let snapshot = snapshot_rent_state(&accounts);
+    **accounts[0].lamports.borrow_mut() = 1379;
+    let result = check_rent_exemption(&accounts, &snapshot);
+    assert_eq!(result, Err(ProgramError::AccountNotRentExempt));
+
+    **accounts[0].lamports.borrow_mut() = 1380;
+    let result = check_rent_exemption(&accounts, &snapshot);
+    assert_eq!(result, Ok(()));
+}
+
+#[test]
+fn it_should_only_check_resized_accounts() {
+    let _guard = setup();
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports: u64 = 5;
+    let mut data: Vec<u8> = vec![];
+    let account_info = create_account_info(&key, &owner, &mut lamports, &mut data);
+    let accounts = vec![account_info];
+
+    // rent paying accounts that aren't resized are left alone
+    let snapshot = snapshot_rent_state(&accounts);
+    assert_eq!(check_rent_exemption(&accounts, &snapshot), Ok(()));
+
+    account_manager::set_data_size(&accounts[0], 10);
+    let result = check_rent_exemption(&accounts, &snapshot);
+    assert_eq!(result, Err(ProgramError::AccountNotRentExempt));
+
+    // closing it is fine
+    **accounts[0].lamports.borrow_mut() = 0;
+    assert_eq!(check_rent_exemption(&accounts, &snapshot), Ok(()));
+    account_manager::clear();
+}
+
+fn create_account_info<'a>(
+    key: &'a Pubkey,
+    owner: &'a Pubkey,
+    lamports: &'a mut u64,
+    data: &'a mut [u8],
+) -> AccountInfo<'a> {
+    AccountInfo {
+        key,
+        is_signer: false,
+        is_writable: true,
+        lamports: Rc::new(RefCell::new(lamports)),
+        data: Rc::new(RefCell::new(data)),
+        owner,
+        executable: false,
+        rent_epoch: 1,
+    }
+}
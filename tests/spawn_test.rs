@@ -0,0 +1,141 @@
+mod common;
+
+use cartesi_solana::{
+    adapter::load_account_info_data, compute_budget::COMPUTATIONAL_BUDGET_EXCEEDED, executor::Executor, log_collector,
+};
+use common::{create_account, sender_pubkey, MyLineReader};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use std::{os::unix::fs::PermissionsExt, str::FromStr, sync::MutexGuard};
+
+const PROGRAM: &str = "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv";
+const ACCOUNT: &str = "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY";
+const HANGING_PROGRAM: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+const FAILING_PROGRAM: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+
+/// Deploys a fake `PROGRAM` that sends back `ACCOUNT` with 777 lamports, a
+/// `HANGING_PROGRAM` that never answers and a `FAILING_PROGRAM` that fails
+/// with `MissingRequiredSignature`.
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup();
+    let bin_path = std::env::temp_dir().join(format!("cartesi-solana-spawn-{}", std::process::id()));
+    std::fs::create_dir_all(&bin_path).unwrap();
+    let script = format!(
+        "#!/bin/sh\ncat > /dev/null\necho 'CPI Response: {{\"compute_units_consumed\":100,\"logs\":[\"Program {program} invoke [1]\",\"Program {program} success\"],\"accounts\":[{{\"key\":\"{account}\",\"owner\":\"{program}\",\"lamports\":777,\"data\":\"AQID\"}}]}}'\n",
+        program = PROGRAM,
+        account = ACCOUNT,
+    );
+    let failing_script = format!(
+        "#!/bin/sh\ncat > /dev/null\necho 'CPI Response: {{\"compute_units_consumed\":100,\"error\":{}}}'\nexit 1\n",
+        u64::from(ProgramError::MissingRequiredSignature),
+    );
+    let scripts = [
+        (PROGRAM, script.as_str()),
+        (HANGING_PROGRAM, "#!/bin/sh\nexec sleep 30\n"),
+        (FAILING_PROGRAM, failing_script.as_str()),
+    ];
+    for (program, script) in scripts {
+        let path = bin_path.join(program);
+        std::fs::write(&path, script).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    std::env::set_var("SOLANA_BIN_PATH", bin_path);
+    std::env::set_var("SOLANA_CPI_TIMEOUT", "1");
+
+    create_account(&Pubkey::from_str(ACCOUNT).unwrap(), 100, system_program::id(), vec![]);
+    guard
+}
+
+#[test]
+fn executor_should_spawn_a_program_other_than_the_host() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str(PROGRAM).unwrap();
+    let mut executor = Executor::create_with_stdin(create_stdin(program_id));
+    executor.host_program_id = Some(Pubkey::new_unique());
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Err(ProgramError::Custom(99)));
+    assert_eq!(result, Ok(()));
+
+    let (data, lamports, owner) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(data, vec![1, 2, 3]);
+    assert_eq!(lamports, 777);
+    assert_eq!(owner, program_id);
+    // the spawned program frames its own logs
+    assert_eq!(
+        log_collector::get_logs(),
+        vec![format!("Program {} invoke [1]", PROGRAM), format!("Program {} success", PROGRAM)]
+    );
+}
+
+#[test]
+fn executor_should_kill_a_spawned_program_that_hangs() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str(HANGING_PROGRAM).unwrap();
+    let mut executor = Executor::create_with_stdin(create_stdin(program_id));
+    executor.host_program_id = Some(Pubkey::new_unique());
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+    assert_eq!(result, Err(COMPUTATIONAL_BUDGET_EXCEEDED));
+    assert!(log_collector::get_logs().contains(&format!("Program {} timed out", HANGING_PROGRAM)));
+
+    let (_, lamports, _) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(lamports, 100);
+}
+
+#[test]
+fn executor_should_return_the_error_of_a_spawned_program() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str(FAILING_PROGRAM).unwrap();
+    let mut executor = Executor::create_with_stdin(create_stdin(program_id));
+    executor.host_program_id = Some(Pubkey::new_unique());
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+}
+
+#[test]
+fn executor_should_reject_a_program_that_is_not_deployed() {
+    let _guard = setup();
+    let program_id = Pubkey::new_unique();
+    let mut executor = Executor::create_with_stdin(create_stdin(program_id));
+    executor.host_program_id = Some(Pubkey::from_str(PROGRAM).unwrap());
+    let result = executor.get_processor_args(|_program_id, _accounts, _data| Ok(()));
+    assert_eq!(result, Err(ProgramError::IncorrectProgramId));
+
+    let (_, lamports, owner) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(lamports, 100);
+    assert_eq!(owner, system_program::id());
+}
+
+#[test]
+fn executor_should_run_the_closure_for_the_host_program() {
+    let _guard = setup();
+    let program_id = Pubkey::from_str(PROGRAM).unwrap();
+    let mut executor = Executor::create_with_stdin(create_stdin(program_id));
+    executor.host_program_id = Some(program_id);
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        **accounts[1].lamports.borrow_mut() = 50;
+        Ok(())
+    });
+    assert_eq!(result, Ok(()));
+
+    let (_, lamports, _) = load_account_info_data(&Pubkey::from_str(ACCOUNT).unwrap());
+    assert_eq!(lamports, 50);
+}
+
+//
+// Helper functions
+//
+
+fn create_stdin(program_id: Pubkey) -> MyLineReader {
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(sender_pubkey(), true),
+            AccountMeta::new(Pubkey::from_str(ACCOUNT).unwrap(), false),
+        ],
+    );
+    common::create_stdin(&[instruction])
+}
@@ -0,0 +1,203 @@
+#![cfg(feature = "spl")]
+
+mod common;
+
+use cartesi_solana::{
+    account_manager,
+    adapter::{eth_address_to_pubkey, load_account_info_data},
+    executor::Executor,
+    owner_manager,
+    spl::{
+        spl_associated_token_account::{get_associated_token_address, instruction as ata_instruction},
+        spl_token::{
+            self,
+            error::TokenError,
+            instruction as token_instruction,
+            state::{Account, Mint},
+        },
+    },
+};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program::invoke,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program,
+};
+use common::{create_account, create_stdin, run_instructions, sender_pubkey};
+use std::{str::FromStr, sync::MutexGuard};
+
+const PROGRAM: &str = "2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv";
+const MINT: &str = "6Tw6Z6SsM3ypmGsB3vpSx8midhhyTvTwdPd7K413LyyY";
+const RECIPIENT: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+
+fn setup() -> MutexGuard<'static, ()> {
+    let guard = common::setup();
+    let mint = Mint {
+        mint_authority: COption::Some(sender_pubkey()),
+        supply: 0,
+        decimals: 6,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut data).unwrap();
+    create_account(&mint_pubkey(), 1_000_000_000, spl_token::id(), data);
+    create_account(&sender_pubkey(), 1_000_000_000, system_program::id(), vec![]);
+    guard
+}
+
+#[test]
+fn executor_should_create_an_associated_token_account_and_mint() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let mint = mint_pubkey();
+    let result = run_instructions(&[
+        ata_instruction::create_associated_token_account(&sender, &sender, &mint, &spl_token::id()),
+        token_instruction::mint_to(
+            &spl_token::id(),
+            &mint,
+            &get_associated_token_address(&sender, &mint),
+            &sender,
+            &[],
+            100,
+        )
+        .unwrap(),
+    ]);
+    assert_eq!(result, Ok(()));
+
+    let (data, lamports, owner) = load_account_info_data(&get_associated_token_address(&sender, &mint));
+    assert_eq!(owner, spl_token::id());
+    assert!(lamports > 0);
+    let account = Account::unpack(&data).unwrap();
+    assert_eq!(account.mint, mint);
+    assert_eq!(account.owner, sender);
+    assert_eq!(account.amount, 100);
+    let (data, _, _) = load_account_info_data(&mint);
+    assert_eq!(Mint::unpack(&data).unwrap().supply, 100);
+}
+
+#[test]
+fn executor_should_serve_the_cpis_to_the_spl_programs() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let mint = mint_pubkey();
+    let recipient = Pubkey::from_str(RECIPIENT).unwrap();
+    let sender_ata = get_associated_token_address(&sender, &mint);
+    let recipient_ata = get_associated_token_address(&recipient, &mint);
+    let result = run_instructions(&[
+        ata_instruction::create_associated_token_account(&sender, &sender, &mint, &spl_token::id()),
+        token_instruction::mint_to(&spl_token::id(), &mint, &sender_ata, &sender, &[], 100).unwrap(),
+    ]);
+    assert_eq!(result, Ok(()));
+
+    let instruction = Instruction::new_with_bytes(
+        Pubkey::from_str(PROGRAM).unwrap(),
+        &[],
+        vec![
+            AccountMeta::new(sender, true),
+            AccountMeta::new(sender_ata, false),
+            AccountMeta::new_readonly(recipient, false),
+            AccountMeta::new(recipient_ata, false),
+            AccountMeta::new_readonly(mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account_id(), false),
+        ],
+    );
+    let amounts = [40, 100];
+    for amount in amounts {
+        let stdin = create_stdin(std::slice::from_ref(&instruction));
+        let mut executor = Executor::create_with_stdin(stdin);
+        let result = executor.get_processor_args(|_program_id, accounts, _data| {
+            let create = ata_instruction::create_associated_token_account_idempotent(
+                accounts[0].key,
+                accounts[2].key,
+                accounts[4].key,
+                &spl_token::id(),
+            );
+            invoke(&create, &accounts[..])?;
+            let transfer = token_instruction::transfer_checked(
+                &spl_token::id(),
+                accounts[1].key,
+                accounts[4].key,
+                accounts[3].key,
+                accounts[0].key,
+                &[],
+                amount,
+                6,
+            )
+            .unwrap();
+            invoke(&transfer, &accounts[..])
+        });
+        owner_manager::clear();
+        account_manager::clear();
+        if amount == 40 {
+            assert_eq!(result, Ok(()));
+        } else {
+            assert_eq!(result, Err(ProgramError::Custom(TokenError::InsufficientFunds as u32)));
+        }
+    }
+
+    let (data, _, _) = load_account_info_data(&sender_ata);
+    assert_eq!(Account::unpack(&data).unwrap().amount, 60);
+    let (data, _, owner) = load_account_info_data(&recipient_ata);
+    assert_eq!(owner, spl_token::id());
+    let account = Account::unpack(&data).unwrap();
+    assert_eq!(account.owner, recipient);
+    assert_eq!(account.amount, 40);
+}
+
+#[test]
+fn executor_should_not_let_a_program_spend_the_tokens_of_a_non_signer() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let mint = mint_pubkey();
+    // an Ethereum user, who doesn't sign the second transaction
+    let victim = eth_address_to_pubkey(&hex::decode("70997970C51812dc3A010C7d01b50e0d17dc79C8").unwrap());
+    let victim_ata = get_associated_token_address(&victim, &mint);
+    let sender_ata = get_associated_token_address(&sender, &mint);
+    let result = run_instructions(&[
+        ata_instruction::create_associated_token_account(&sender, &victim, &mint, &spl_token::id()),
+        ata_instruction::create_associated_token_account(&sender, &sender, &mint, &spl_token::id()),
+        token_instruction::mint_to(&spl_token::id(), &mint, &victim_ata, &sender, &[], 100).unwrap(),
+    ]);
+    assert_eq!(result, Ok(()));
+
+    let instruction = Instruction::new_with_bytes(
+        Pubkey::from_str(PROGRAM).unwrap(),
+        &[],
+        vec![
+            AccountMeta::new(victim_ata, false),
+            AccountMeta::new(sender_ata, false),
+            AccountMeta::new_readonly(victim, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    );
+    let mut executor = Executor::create_with_stdin(create_stdin(&[instruction]));
+    let result = executor.get_processor_args(|_program_id, accounts, _data| {
+        let transfer =
+            token_instruction::transfer(&spl_token::id(), accounts[0].key, accounts[1].key, accounts[2].key, &[], 100)
+                .unwrap();
+        invoke(&transfer, &accounts[..])
+    });
+    owner_manager::clear();
+    account_manager::clear();
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    let (data, _, _) = load_account_info_data(&victim_ata);
+    assert_eq!(Account::unpack(&data).unwrap().amount, 100);
+}
+
+//
+// Helper functions
+//
+
+fn mint_pubkey() -> Pubkey {
+    Pubkey::from_str(MINT).unwrap()
+}
+
+fn spl_associated_token_account_id() -> Pubkey {
+    cartesi_solana::spl::spl_associated_token_account::id()
+}
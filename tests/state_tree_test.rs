@@ -0,0 +1,215 @@
+mod common;
+
+use std::{fs, sync::Arc};
+
+use cartesi_solana::{
+    account_manager::{self, create_account_manager, AccountFileData, AccountStore, MemoryAccountStore},
+    state_tree::{self, Hash32, Node, ZERO_HASH},
+};
+use solana_program::pubkey::Pubkey;
+
+
+#[test]
+fn it_should_match_the_root_computed_from_scratch() {
+    let store = MemoryAccountStore::new();
+    assert_eq!(store.state_root().unwrap(), ZERO_HASH);
+
+    let accounts = create_accounts(20);
+    for (key, account) in accounts.iter() {
+        store.write_account(key, account).unwrap();
+    }
+    assert_eq!(store.state_root().unwrap(), root_from_scratch(&accounts));
+
+    for (key, _) in accounts.iter().take(15) {
+        store.delete_account(key).unwrap();
+    }
+    assert_eq!(store.state_root().unwrap(), root_from_scratch(&accounts[15..]));
+
+    for (key, _) in accounts.iter().skip(15) {
+        store.delete_account(key).unwrap();
+    }
+    assert_eq!(store.state_root().unwrap(), ZERO_HASH);
+}
+
+#[test]
+fn it_should_not_depend_on_the_order_of_the_writes() {
+    let accounts = create_accounts(10);
+    let store = MemoryAccountStore::new();
+    for (key, account) in accounts.iter() {
+        store.write_account(key, account).unwrap();
+    }
+    let other_store = MemoryAccountStore::new();
+    for (key, account) in accounts.iter().rev() {
+        other_store.write_account(key, &AccountFileData { lamports: 1, ..account.clone() }).unwrap();
+        other_store.write_account(key, account).unwrap();
+    }
+    assert_eq!(store.state_root().unwrap(), other_store.state_root().unwrap());
+}
+
+#[test]
+fn it_should_keep_the_same_root_in_the_file_store() {
+    let _guard = common::setup_data_path();
+    let accounts = create_accounts(8);
+    let file_store = create_account_manager();
+    let memory_store = MemoryAccountStore::new();
+    for (key, account) in accounts.iter() {
+        file_store.write_account(key, account).unwrap();
+        memory_store.write_account(key, account).unwrap();
+    }
+    file_store.delete_account(&accounts[0].0).unwrap();
+    memory_store.delete_account(&accounts[0].0).unwrap();
+    assert_eq!(file_store.state_root().unwrap(), memory_store.state_root().unwrap());
+
+    account_manager::set_account_store(Arc::new(memory_store));
+    let memory_root = account_manager::get_state_root();
+    account_manager::reset_account_store();
+    assert_eq!(account_manager::get_state_root(), memory_root);
+}
+
+#[test]
+fn it_should_compute_the_root_of_accounts_saved_before_the_state_tree() {
+    let _guard = common::setup_data_path();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let accounts = create_accounts(5);
+    for (key, account) in accounts.iter() {
+        let contents = serde_json::to_string(account).unwrap();
+        fs::write(format!("{}/{}.json", data_path, key), contents).unwrap();
+    }
+    assert_eq!(account_manager::get_state_root(), root_from_scratch(&accounts));
+}
+
+#[test]
+fn it_should_prove_the_accounts_and_the_missing_ones() {
+    let accounts = create_accounts(12);
+    let store = MemoryAccountStore::new();
+    for (key, account) in accounts.iter() {
+        store.write_account(key, account).unwrap();
+    }
+    let root = store.state_root().unwrap();
+
+    for (key, account) in accounts.iter() {
+        let proof = store.prove_account(key).unwrap();
+        assert_eq!(proof.account.as_ref().unwrap().lamports, account.lamports);
+        assert!(proof.verify(&root));
+    }
+
+    let missing = store.prove_account(&Pubkey::new_unique()).unwrap();
+    assert!(missing.account.is_none());
+    assert!(missing.verify(&root));
+
+    // a proof of the account doesn't prove it is missing
+    let mut excluded = store.prove_account(&accounts[0].0).unwrap();
+    excluded.account = None;
+    assert!(!excluded.verify(&root));
+}
+
+#[test]
+fn it_should_not_verify_a_tampered_proof() {
+    let accounts = create_accounts(5);
+    let store = MemoryAccountStore::new();
+    for (key, account) in accounts.iter() {
+        store.write_account(key, account).unwrap();
+    }
+    let root = store.state_root().unwrap();
+    let proof = store.prove_account(&accounts[2].0).unwrap();
+
+    let mut tampered = proof.clone();
+    tampered.account.as_mut().unwrap().lamports += 1;
+    assert!(!tampered.verify(&root));
+
+    let mut tampered = proof.clone();
+    tampered.proof.siblings[0][0] ^= 1;
+    assert!(!tampered.verify(&root));
+
+    let mut tampered = proof.clone();
+    tampered.proof.siblings.push(ZERO_HASH);
+    assert!(!tampered.verify(&root));
+}
+
+#[test]
+fn it_should_prove_the_accounts_of_the_file_store() {
+    let _guard = common::setup_data_path();
+    let accounts = create_accounts(6);
+    let file_store = create_account_manager();
+    for (key, account) in accounts.iter() {
+        file_store.write_account(key, account).unwrap();
+    }
+    let proof = file_store.prove_account(&accounts[3].0).unwrap();
+    assert!(proof.verify(&file_store.state_root().unwrap()));
+
+    let encoded = proof.abi_encode();
+    let account = proof.account.as_ref().unwrap();
+    assert_eq!(&encoded[0..32], accounts[3].0.as_ref());
+    assert_eq!(encoded[63], 1);
+    assert_eq!(&encoded[64..96], account.owner.as_ref());
+    assert_eq!(&encoded[120..128], &account.lamports.to_be_bytes());
+    assert_eq!(&encoded[160..192], &proof.proof.bitmap);
+    let siblings_offset = u64::from_be_bytes(encoded[216..224].try_into().unwrap()) as usize;
+    assert_eq!(encoded.len(), siblings_offset + 32 + 32 * proof.proof.siblings.len());
+}
+#[test]
+fn it_should_rebuild_the_state_tree_of_an_interrupted_commit() {
+    let _guard = common::setup_data_path();
+    let data_path = std::env::var("SOLANA_DATA_PATH").unwrap();
+    let accounts = create_accounts(2);
+    let file_store = create_account_manager();
+    for (key, account) in accounts.iter() {
+        file_store.write_account(key, account).unwrap();
+    }
+
+    // crashed deleting the first account: both leaves are gone, the one left
+    // wasn't moved up yet and the account file is still there
+    let writes: Vec<account_manager::AccountWrite> = vec![(accounts[0].0, None)];
+    fs::write(format!("{}/.journal.json", data_path), serde_json::to_string(&writes).unwrap()).unwrap();
+    for path in fs::read_dir(format!("{}/.state", data_path)).unwrap() {
+        let path = path.unwrap().path();
+        let node: Node = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        if let Node::Leaf { .. } = node {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    let file_store = account_manager::create_file_account_manager();
+    assert!(file_store.read_account(&accounts[0].0).is_err());
+    assert_eq!(file_store.state_root().unwrap(), root_from_scratch(&accounts[1..]));
+}
+
+
+//
+// Helper functions
+//
+
+fn create_accounts(total: usize) -> Vec<(Pubkey, AccountFileData)> {
+    (0..total)
+        .map(|i| {
+            let account = AccountFileData {
+                owner: Pubkey::new_unique(),
+                data: vec![i as u8; i],
+                lamports: 1000 + i as u64,
+            };
+            (Pubkey::new_unique(), account)
+        })
+        .collect()
+}
+
+/// Hashes every level of the 256 levels tree.
+fn root_from_scratch(accounts: &[(Pubkey, AccountFileData)]) -> Hash32 {
+    let leaves: Vec<(Hash32, Hash32)> = accounts
+        .iter()
+        .map(|(key, account)| (key.to_bytes(), state_tree::leaf_hash(key, account)))
+        .collect();
+    subtree_hash(&leaves, 0)
+}
+
+fn subtree_hash(leaves: &[(Hash32, Hash32)], depth: usize) -> Hash32 {
+    if leaves.is_empty() {
+        return ZERO_HASH;
+    }
+    if depth == 256 {
+        return leaves[0].1;
+    }
+    let bit = |key: &Hash32| (key[depth / 8] >> (7 - depth % 8)) & 1;
+    let left: Vec<(Hash32, Hash32)> = leaves.iter().filter(|(key, _)| bit(key) == 0).cloned().collect();
+    let right: Vec<(Hash32, Hash32)> = leaves.iter().filter(|(key, _)| bit(key) == 1).cloned().collect();
+    state_tree::hash_pair(&subtree_hash(&left, depth + 1), &subtree_hash(&right, depth + 1))
+}
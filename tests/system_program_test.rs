@@ -0,0 +1,174 @@
+mod common;
+
+use std::str::FromStr;
+
+use cartesi_solana::{
+    account_manager,
+    adapter::{eth_address_to_pubkey, load_account_info_data},
+    executor::Executor,
+    owner_manager,
+};
+use common::{create_account, create_stdin, run_instructions, sender_pubkey, setup};
+use solana_program::{
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction::{self, SystemError},
+    system_program,
+};
+
+#[test]
+fn it_should_transfer_lamports() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let recipient = Pubkey::new_unique();
+    create_account(&sender, 1_000_000_000, system_program::id(), vec![]);
+
+    let result = run_instructions(&[system_instruction::transfer(&sender, &recipient, 1000)]);
+    assert_eq!(result, Ok(()));
+    let (_, lamports, owner) = load_account_info_data(&sender);
+    assert_eq!(lamports, 1_000_000_000 - 1000);
+    assert_eq!(owner, system_program::id());
+    let (_, lamports, _) = load_account_info_data(&recipient);
+    assert_eq!(lamports, 1000);
+}
+
+#[test]
+fn it_should_check_the_source_of_a_transfer() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let other = Pubkey::new_unique();
+    create_account(&sender, 1000, system_program::id(), vec![1]);
+    create_account(&other, 1000, system_program::id(), vec![]);
+
+    // the source can't carry data
+    let result = run_instructions(&[system_instruction::transfer(&sender, &other, 1)]);
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+
+    // nor spend from others
+    let mut instruction = system_instruction::transfer(&other, &sender, 1);
+    instruction.accounts[0].is_signer = false;
+    let result = run_instructions(&[instruction]);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+
+    let result = run_instructions(&[system_instruction::transfer(&sender, &other, 0)]);
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let (_, lamports, _) = load_account_info_data(&other);
+    assert_eq!(lamports, 1000);
+}
+
+#[test]
+fn it_should_create_an_account_with_seed() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let owner = Pubkey::new_unique();
+    let address = Pubkey::create_with_seed(&sender, "vault", &owner).unwrap();
+    create_account(&sender, 1_000_000_000, system_program::id(), vec![]);
+
+    let instruction = system_instruction::create_account_with_seed(&sender, &address, &sender, "vault", 5000, 42, &owner);
+    assert_eq!(run_instructions(&[instruction]), Ok(()));
+    let (data, lamports, account_owner) = load_account_info_data(&address);
+    assert_eq!(data, vec![0; 42]);
+    assert_eq!(lamports, 5000);
+    assert_eq!(account_owner, owner);
+
+    let other_address = Pubkey::create_with_seed(&sender, "other", &owner).unwrap();
+    let instruction = system_instruction::create_account_with_seed(&sender, &other_address, &sender, "vault", 5000, 42, &owner);
+    let result = run_instructions(&[instruction]);
+    assert_eq!(result, Err(ProgramError::Custom(SystemError::AddressWithSeedMismatch as u32)));
+}
+
+#[test]
+fn it_should_create_a_pda_account_through_cpi() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    let program_id = Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap();
+    let (pda, _) = Pubkey::find_program_address(&[b"state"], &program_id);
+    create_account(&sender, 1_000_000_000, system_program::id(), vec![]);
+
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(sender, true),
+            AccountMeta::new(pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let result = run_program_instruction(instruction, init_entrypoint);
+    assert_eq!(result, Ok(()));
+    let (data, lamports, owner) = load_account_info_data(&pda);
+    assert_eq!(data, vec![0; 16]);
+    assert_eq!(lamports, 2_000_000);
+    assert_eq!(owner, program_id);
+
+    // the account exists now
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &[],
+        vec![
+            AccountMeta::new(sender, true),
+            AccountMeta::new(pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let result = run_program_instruction(instruction, init_entrypoint);
+    assert_eq!(result, Err(ProgramError::Custom(SystemError::AccountAlreadyInUse as u32)));
+}
+
+#[test]
+fn it_should_not_transfer_from_a_non_signer_through_cpi() {
+    let _guard = setup();
+    let sender = sender_pubkey();
+    // an Ethereum user, who doesn't sign this transaction
+    let victim = eth_address_to_pubkey(&hex::decode("70997970C51812dc3A010C7d01b50e0d17dc79C8").unwrap());
+    let thief = Pubkey::new_unique();
+    create_account(&sender, 1_000_000_000, system_program::id(), vec![]);
+    create_account(&victim, 1_000_000, system_program::id(), vec![]);
+
+    let instruction = Instruction::new_with_bytes(
+        Pubkey::from_str("2QB8wEBJ8jjMQuZPvj3jaZP7JJb5j21u4xbxTnwsZRfv").unwrap(),
+        &[],
+        vec![
+            AccountMeta::new(victim, false),
+            AccountMeta::new(thief, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let result = run_program_instruction(instruction, steal_entrypoint);
+    assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+    let (_, lamports, _) = load_account_info_data(&victim);
+    assert_eq!(lamports, 1_000_000);
+    let (_, lamports, _) = load_account_info_data(&thief);
+    assert_eq!(lamports, 0);
+}
+
+//
+// Helper functions
+//
+
+fn steal_entrypoint<'a>(_program_id: &'a Pubkey, accounts: &'a Vec<solana_program::account_info::AccountInfo<'a>>) -> ProgramResult {
+    let instruction = system_instruction::transfer(accounts[0].key, accounts[1].key, 1_000_000);
+    invoke(&instruction, accounts)
+}
+
+/// Like Anchor's `init`
+fn init_entrypoint<'a>(program_id: &'a Pubkey, accounts: &'a Vec<solana_program::account_info::AccountInfo<'a>>) -> ProgramResult {
+    assert!(accounts[2].executable);
+    let (pda, bump) = Pubkey::find_program_address(&[b"state"], program_id);
+    let instruction = system_instruction::create_account(accounts[0].key, &pda, 2_000_000, 16, program_id);
+    invoke_signed(&instruction, accounts, &[&[b"state", &[bump]]])
+}
+
+fn run_program_instruction(
+    instruction: Instruction,
+    entrypoint: for<'a> fn(&'a Pubkey, &'a Vec<solana_program::account_info::AccountInfo<'a>>) -> ProgramResult,
+) -> ProgramResult {
+    let mut executor = Executor::create_with_stdin(create_stdin(&[instruction]));
+    let result = executor.get_processor_args(|program_id, accounts, _data| entrypoint(program_id, accounts));
+    owner_manager::clear();
+    account_manager::clear();
+    result
+}